@@ -0,0 +1,269 @@
+//! # Handle basic blocks
+//!
+//! This module groups the instructions of a `ControlFlowGraph` into basic
+//! blocks. A basic block is a maximal sequence of instructions, which is
+//! always executed from its first to its last instruction.
+//!
+//! A new block starts (leader) at:
+//! - the first instruction of the code segment
+//! - every target of a non-trivial edge (branch/jump targets, function
+//!   entries and call returns)
+//! - every instruction after a terminator (`beq`, `jal`, `jalr` and exit
+//!   `ecall`) or after a gap in the code
+
+use crate::cfg::{next_node, ControlFlowGraph, EdgeType, INSTRUCTION_SIZE};
+use crate::dot;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Graph;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// A sequence of instructions, identified by their `NodeIndex` in the
+/// `ControlFlowGraph`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BasicBlock {
+    pub instructions: Vec<NodeIndex>,
+    /// Virtual address of the first instruction
+    pub start_address: u64,
+    /// Virtual address right after the last instruction
+    pub end_address: u64,
+}
+
+#[allow(dead_code)]
+impl BasicBlock {
+    /// `NodeIndex` of the leader of this block
+    pub fn first(&self) -> NodeIndex {
+        self.instructions[0]
+    }
+
+    /// `NodeIndex` of the terminator of this block
+    pub fn last(&self) -> NodeIndex {
+        self.instructions[self.instructions.len() - 1]
+    }
+
+    /// Check if `address` points to an instruction of this block
+    pub fn contains_address(&self, address: u64) -> bool {
+        self.start_address <= address && address < self.end_address
+    }
+}
+
+pub type BasicBlockGraph = Graph<BasicBlock, EdgeType>;
+
+/// Basic block graph together with the mapping from instructions to blocks
+#[allow(dead_code)]
+pub struct BasicBlocks {
+    pub graph: BasicBlockGraph,
+    block_of: HashMap<NodeIndex, NodeIndex>,
+}
+
+#[allow(dead_code)]
+impl BasicBlocks {
+    /// Group all instructions of `cfg` into basic blocks.
+    pub fn build(cfg: &ControlFlowGraph) -> Self {
+        let leaders = find_leaders(cfg);
+
+        let mut graph = BasicBlockGraph::new();
+        let mut block_of = HashMap::new();
+        let mut current: Option<NodeIndex> = None;
+
+        cfg.node_indices().for_each(|idx| {
+            let block = match current {
+                Some(block) if !leaders.contains(&idx) => block,
+                _ => graph.add_node(BasicBlock {
+                    instructions: vec![],
+                    start_address: cfg[idx].address,
+                    end_address: cfg[idx].address,
+                }),
+            };
+
+            graph[block].instructions.push(idx);
+            graph[block].end_address = cfg[idx].address + INSTRUCTION_SIZE;
+            block_of.insert(idx, block);
+            current = Some(block);
+        });
+
+        graph.node_indices().for_each(|block| {
+            let last = graph[block].last();
+
+            cfg.edges(last)
+                .map(|e| (block_of[&e.target()], *e.weight()))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .for_each(|(target, weight)| {
+                    graph.add_edge(block, target, weight);
+                });
+        });
+
+        Self { graph, block_of }
+    }
+
+    /// Get the block containing the instruction `idx` of the `ControlFlowGraph`.
+    pub fn block_of(&self, idx: NodeIndex) -> Option<NodeIndex> {
+        self.block_of.get(&idx).copied()
+    }
+
+    /// Get the block containing the instruction at virtual `address`
+    /// (blocks are sorted by their address).
+    pub fn block_at_address(&self, address: u64) -> Option<NodeIndex> {
+        let blocks = self.graph.raw_nodes();
+
+        let candidate = match blocks.binary_search_by_key(&address, |b| b.weight.start_address) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+
+        Some(NodeIndex::new(candidate)).filter(|block| self.graph[*block].contains_address(address))
+    }
+}
+
+/// Check if control always falls through from `idx` to the instruction at
+/// the next address.
+fn falls_through(cfg: &ControlFlowGraph, idx: NodeIndex) -> bool {
+    let mut edges = cfg.edges(idx);
+
+    match (edges.next(), edges.next()) {
+        (Some(e), None) => {
+            matches!(e.weight(), EdgeType::Fallthrough | EdgeType::SyscallExit)
+                && next_node(cfg, idx) == Some(e.target())
+        }
+        _ => false,
+    }
+}
+
+/// Compute the first instruction of every basic block
+fn find_leaders(cfg: &ControlFlowGraph) -> BTreeSet<NodeIndex> {
+    // every instruction, which is not entered by falling through from the
+    // previous address, starts a block (this includes instructions after gaps)
+    let followers = cfg
+        .node_indices()
+        .filter(|idx| falls_through(cfg, *idx))
+        .filter_map(|idx| next_node(cfg, idx))
+        .collect::<BTreeSet<_>>();
+
+    let mut leaders = cfg
+        .node_indices()
+        .filter(|idx| !followers.contains(idx))
+        .collect::<BTreeSet<_>>();
+
+    cfg.node_indices()
+        .filter(|idx| !falls_through(cfg, *idx))
+        .for_each(|idx| leaders.extend(cfg.neighbors(idx)));
+
+    leaders
+}
+
+/// Write the basic blocks of `cfg` to a dot file at `file` Path. Blocks are
+/// clustered by the `cluster` of their first instruction (see
+/// `dot::write_graph`).
+pub fn write_to_file(
+    blocks: &BasicBlocks,
+    cfg: &ControlFlowGraph,
+    cluster: impl Fn(NodeIndex) -> Option<(usize, String)>,
+    file: &Path,
+) -> Result<(), std::io::Error> {
+    dot::write_graph(
+        &blocks.graph,
+        |block| {
+            blocks.graph[block]
+                .instructions
+                .iter()
+                .map(|idx| format!("{:?}\n", cfg[*idx]))
+                .collect()
+        },
+        dot::edge_label,
+        |block| cluster(blocks.graph[block].first()),
+        &BTreeSet::new(),
+        file,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+
+    #[test]
+    fn can_split_instructions_into_basic_blocks() {
+        let binary = assemble(&[
+            0x00a00293, // addi t0, zero, 10
+            0x00a28463, // beq t0, a0, 8
+            0x00000513, // addi a0, zero, 0
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+        ]);
+
+        let graph = cfg::build(binary.as_slice(), 0);
+        let blocks = BasicBlocks::build(&graph);
+
+        let ranges = blocks
+            .graph
+            .node_indices()
+            .map(|b| {
+                let block = &blocks.graph[b];
+                (block.start_address, block.end_address)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(ranges, vec![(0, 8), (8, 12), (12, 20)]);
+        assert_eq!(blocks.graph.edge_count(), 3, "beq and fall through edges");
+
+        let entry = blocks.block_at_address(4).unwrap();
+        let exit = blocks.block_at_address(16).unwrap();
+
+        assert_eq!(blocks.block_of(NodeIndex::new(0)), Some(entry));
+        assert!(blocks.graph.find_edge(entry, exit).is_some());
+        assert_eq!(
+            blocks.graph.neighbors(exit).count(),
+            0,
+            "exit has no successor"
+        );
+    }
+
+    #[test]
+    fn can_look_up_blocks_by_virtual_address() {
+        let binary = assemble(&[
+            0x00a28463, // beq t0, a0, 8
+            0x00000513, // addi a0, zero, 0
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+        ]);
+
+        let graph = cfg::build(binary.as_slice(), 0x10000);
+        let blocks = BasicBlocks::build(&graph);
+
+        let second = blocks.block_at_address(0x10004).unwrap();
+
+        assert_eq!(blocks.graph[second].start_address, 0x10004);
+        assert_eq!(blocks.graph[second].end_address, 0x10008);
+        assert_eq!(
+            blocks.block_at_address(0x1000c),
+            blocks.block_of(NodeIndex::new(3))
+        );
+        assert_eq!(blocks.block_at_address(0x10010), None);
+        assert_eq!(blocks.block_at_address(0x0fffc), None);
+    }
+
+    #[test]
+    fn call_return_sites_are_leaders() {
+        let binary = assemble(&[
+            0x00c000ef, // jal ra, 12
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00a00293, // addi t0, zero, 10
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+
+        let graph = cfg::build(binary.as_slice(), 0);
+        let blocks = BasicBlocks::build(&graph);
+
+        let leaders = blocks
+            .graph
+            .node_indices()
+            .map(|b| blocks.graph[b].start_address)
+            .collect::<Vec<_>>();
+
+        assert_eq!(leaders, vec![0, 4, 12]);
+    }
+}
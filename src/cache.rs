@@ -0,0 +1,411 @@
+//! # Handle the persistent cache of control flow graphs
+//!
+//! Building a `ControlFlowGraph` (discovery of instructions, value-set
+//! analysis, syscall annotation) is expensive, hence `cfg::build_from_file`
+//! stores every graph in a cache directory and reuses it for the same binary.
+//!
+//! A cache file is named by the content hash of the binary and the discovery
+//! `Strategy`. Its header records the monster version together with the
+//! content hash of the monster executable, the format version and the content
+//! hash of the binary, so that changes of either one invalidate it. Hence
+//! every rebuild of monster (e.g. a changed analysis) starts with an empty
+//! cache. The file itself is line based:
+//! - metadata of the ELF image (entry, code segment and function symbols)
+//! - every node with its address and syscall numbers (instructions are
+//!   decoded from the code segment again)
+//! - every edge with source, target and kind
+//! - an end marker with the number of nodes and edges (truncated files are
+//!   rejected)
+//!
+//! Files are written to a temporary file first and renamed afterwards, hence
+//! concurrent runs never read partially written files.
+//!
+//! The cache directory is `$MONSTER_CACHE_DIR`, `$XDG_CACHE_HOME/monster` or
+//! `$HOME/.cache/monster`. Caching is disabled, if `$MONSTER_NO_CACHE` is set,
+//! if the monster executable cannot be read, and in tests.
+
+use crate::cfg::{ControlFlowGraph, EdgeType, Node, Strategy, INSTRUCTION_SIZE};
+use crate::elf::{ElfMetadata, Symbol};
+use crate::value_analysis::ValueSet;
+use byteorder::{ByteOrder, LittleEndian};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use riscv_decode::decode;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+/// Version of the file format (has to be increased whenever it changes)
+const FORMAT_VERSION: u32 = 1;
+
+/// Compute the FNV-1a hash of `bytes` (stable across platforms and builds).
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Get the version of the running monster build, which is the package
+/// version and the content hash of the executable.
+fn version() -> Option<String> {
+    let executable = fs::read(env::current_exe().ok()?).ok()?;
+
+    Some(format!(
+        "{} build {:016x}",
+        env!("CARGO_PKG_VERSION"),
+        content_hash(executable.as_slice())
+    ))
+}
+
+fn cache_directory() -> Option<PathBuf> {
+    // tests must not read or pollute the cache of the user
+    if cfg!(test) || env::var_os("MONSTER_NO_CACHE").is_some() {
+        return None;
+    }
+
+    env::var_os("MONSTER_CACHE_DIR")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("XDG_CACHE_HOME").map(|dir| PathBuf::from(dir).join("monster")))
+        .or_else(|| env::var_os("HOME").map(|dir| PathBuf::from(dir).join(".cache/monster")))
+}
+
+fn cache_file(hash: u64, strategy: Strategy) -> Option<PathBuf> {
+    cache_directory().map(|dir| dir.join(format!("{:016x}-{:?}.cfg", hash, strategy)))
+}
+
+fn serialize_edge_type(edge_type: EdgeType) -> String {
+    match edge_type {
+        EdgeType::Fallthrough => String::from("fallthrough"),
+        EdgeType::BranchTaken => String::from("branch-taken"),
+        EdgeType::BranchNotTaken => String::from("branch-not-taken"),
+        EdgeType::Jump => String::from("jump"),
+        EdgeType::Call => String::from("call"),
+        EdgeType::Return(call_site) => format!("return {}", call_site.index()),
+        EdgeType::SyscallExit => String::from("syscall-exit"),
+    }
+}
+
+fn deserialize_edge_type(fields: &[&str]) -> Option<EdgeType> {
+    Some(match fields {
+        ["fallthrough"] => EdgeType::Fallthrough,
+        ["branch-taken"] => EdgeType::BranchTaken,
+        ["branch-not-taken"] => EdgeType::BranchNotTaken,
+        ["jump"] => EdgeType::Jump,
+        ["call"] => EdgeType::Call,
+        ["return", call_site] => EdgeType::Return(NodeIndex::new(call_site.parse().ok()?)),
+        ["syscall-exit"] => EdgeType::SyscallExit,
+        _ => return None,
+    })
+}
+
+fn serialize_syscall(syscall: &Option<ValueSet>) -> String {
+    match syscall {
+        None => String::from("-"),
+        Some(ValueSet::Top) => String::from("?"),
+        Some(ValueSet::Values(ids)) => ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+fn deserialize_syscall(field: &str) -> Option<Option<ValueSet>> {
+    Some(match field {
+        "-" => None,
+        "?" => Some(ValueSet::Top),
+        ids => Some(ValueSet::Values(
+            ids.split(',')
+                .map(|id| id.parse().ok())
+                .collect::<Option<_>>()?,
+        )),
+    })
+}
+
+/// Serialize `graph` and `elf_metadata` of the binary with content `hash`,
+/// which are computed by monster `version`.
+fn serialize(
+    graph: &ControlFlowGraph,
+    elf_metadata: &ElfMetadata,
+    version: &str,
+    hash: u64,
+    strategy: Strategy,
+) -> String {
+    let mut lines = vec![
+        format!("monster-cfg {} format {}", version, FORMAT_VERSION),
+        format!("hash {:016x}", hash),
+        format!("strategy {:?}", strategy),
+        format!("entry {}", elf_metadata.entry_address),
+        format!(
+            "code {} {}",
+            elf_metadata.code_start, elf_metadata.code_length
+        ),
+    ];
+
+    lines.extend(
+        elf_metadata
+            .functions
+            .iter()
+            .map(|s| format!("function {} {} {}", s.address, s.size, s.name)),
+    );
+
+    lines.extend(graph.node_indices().map(|idx| {
+        format!(
+            "node {} {}",
+            graph[idx].address,
+            serialize_syscall(&graph[idx].syscall)
+        )
+    }));
+
+    lines.extend(graph.edge_references().map(|e| {
+        format!(
+            "edge {} {} {}",
+            e.source().index(),
+            e.target().index(),
+            serialize_edge_type(*e.weight())
+        )
+    }));
+
+    lines.push(format!("end {} {}", graph.node_count(), graph.edge_count()));
+
+    lines.join("\n")
+}
+
+/// Deserialize a graph of the binary with content `hash` and code segment
+/// `code`, which was computed by monster `version` (`None` if the content is
+/// outdated or corrupted).
+fn deserialize(
+    content: &str,
+    code: &[u8],
+    version: &str,
+    hash: u64,
+    strategy: Strategy,
+) -> Option<(ControlFlowGraph, ElfMetadata)> {
+    let mut lines = content.lines();
+
+    if lines.next()? != format!("monster-cfg {} format {}", version, FORMAT_VERSION)
+        || lines.next()? != format!("hash {:016x}", hash)
+        || lines.next()? != format!("strategy {:?}", strategy)
+    {
+        return None;
+    }
+
+    let mut graph = ControlFlowGraph::new();
+    let mut elf_metadata = ElfMetadata {
+        entry_address: 0,
+        code_length: 0,
+        code_start: 0,
+        functions: vec![],
+    };
+
+    let mut complete = false;
+
+    for line in lines {
+        if complete {
+            return None;
+        }
+
+        let fields = line.splitn(4, ' ').collect::<Vec<_>>();
+
+        match fields.as_slice() {
+            ["entry", address] => elf_metadata.entry_address = address.parse().ok()?,
+            ["code", start, length] => {
+                elf_metadata.code_start = start.parse().ok()?;
+                elf_metadata.code_length = length.parse().ok()?;
+            }
+            ["function", address, size, name] => elf_metadata.functions.push(Symbol {
+                name: String::from(*name),
+                address: address.parse().ok()?,
+                size: size.parse().ok()?,
+            }),
+            ["node", address, syscall] => {
+                let address = address.parse::<u64>().ok()?;
+                let offset = address.checked_sub(elf_metadata.code_start)? as usize;
+                let word = code.get(offset..offset + INSTRUCTION_SIZE as usize)?;
+
+                let mut node = Node::new(address, decode(LittleEndian::read_u32(word)).ok()?);
+                node.syscall = deserialize_syscall(syscall)?;
+
+                graph.add_node(node);
+            }
+            ["edge", source, target, kind] => {
+                let kind = kind.split(' ').collect::<Vec<_>>();
+
+                let source = NodeIndex::new(source.parse().ok()?);
+                let target = NodeIndex::new(target.parse().ok()?);
+
+                let kind = deserialize_edge_type(kind.as_slice())?;
+                let in_graph = |idx: NodeIndex| idx.index() < graph.node_count();
+
+                if !in_graph(source) || !in_graph(target) {
+                    return None;
+                }
+
+                if let EdgeType::Return(call_site) = kind {
+                    if !in_graph(call_site) {
+                        return None;
+                    }
+                }
+
+                graph.add_edge(source, target, kind);
+            }
+            ["end", nodes, edges] => {
+                if nodes.parse::<usize>().ok()? != graph.node_count()
+                    || edges.parse::<usize>().ok()? != graph.edge_count()
+                {
+                    return None;
+                }
+
+                complete = true;
+            }
+            _ => return None,
+        }
+    }
+
+    if !complete {
+        return None;
+    }
+
+    Some((graph, elf_metadata))
+}
+
+/// Load the cached graph of `binary` with code segment `code`.
+pub fn load(
+    binary: &[u8],
+    code: &[u8],
+    strategy: Strategy,
+) -> Option<(ControlFlowGraph, ElfMetadata)> {
+    let hash = content_hash(binary);
+    let content = fs::read_to_string(cache_file(hash, strategy)?).ok()?;
+
+    deserialize(&content, code, &version()?, hash, strategy)
+}
+
+/// Store `graph` of `binary` in the cache (failures are ignored, because the
+/// cache is an optimization only). The file is replaced atomically.
+pub fn store(
+    binary: &[u8],
+    graph: &ControlFlowGraph,
+    elf_metadata: &ElfMetadata,
+    strategy: Strategy,
+) {
+    let hash = content_hash(binary);
+
+    if let (Some(file), Some(version)) = (cache_file(hash, strategy), version()) {
+        if let Some(dir) = file.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+
+        let tmp = file.with_extension(format!("cfg.{}.tmp", process::id()));
+
+        if fs::write(
+            &tmp,
+            serialize(graph, elf_metadata, &version, hash, strategy),
+        )
+        .is_err()
+            || fs::rename(&tmp, &file).is_err()
+        {
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+
+    #[test]
+    fn can_restore_cached_graphs() {
+        let code = assemble(&[
+            0x008000ef, // jal ra, 8
+            0x00000073, // ecall
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+        let graph = cfg::build(code.as_slice(), 0x10000);
+        let elf_metadata = ElfMetadata {
+            entry_address: 0x10000,
+            code_length: 12,
+            code_start: 0x10000,
+            functions: vec![Symbol {
+                name: String::from("main function"),
+                address: 0x10000,
+                size: 12,
+            }],
+        };
+
+        let version = "1.0.0 build 7";
+        let content = serialize(&graph, &elf_metadata, version, 42, Strategy::LinearSweep);
+
+        let (restored, restored_metadata) = deserialize(
+            &content,
+            code.as_slice(),
+            version,
+            42,
+            Strategy::LinearSweep,
+        )
+        .unwrap();
+
+        assert_eq!(format!("{:?}", restored), format!("{:?}", graph));
+        assert_eq!(restored_metadata.functions, elf_metadata.functions);
+        assert_eq!(restored_metadata.code_start, 0x10000);
+
+        assert!(
+            deserialize(
+                &content,
+                code.as_slice(),
+                version,
+                43,
+                Strategy::LinearSweep
+            )
+            .is_none(),
+            "binary changed"
+        );
+        assert!(
+            deserialize(
+                &content,
+                code.as_slice(),
+                "1.0.0 build 8",
+                42,
+                Strategy::LinearSweep
+            )
+            .is_none(),
+            "monster changed"
+        );
+        assert!(deserialize(
+            &content,
+            code.as_slice(),
+            version,
+            42,
+            Strategy::RecursiveTraversal
+        )
+        .is_none());
+
+        let truncated = &content[..content.rfind("\nedge").unwrap()];
+
+        assert!(
+            deserialize(
+                truncated,
+                code.as_slice(),
+                version,
+                42,
+                Strategy::LinearSweep
+            )
+            .is_none(),
+            "file truncated"
+        );
+        assert!(
+            deserialize(
+                &content.replace("return 0", "return 7"),
+                code.as_slice(),
+                version,
+                42,
+                Strategy::LinearSweep
+            )
+            .is_none(),
+            "call site out of bounds"
+        );
+        assert!(cache_directory().is_none(), "tests never use the cache");
+    }
+}
@@ -0,0 +1,370 @@
+//! # Handle call graphs
+//!
+//! This module identifies the functions of a program and the calls between
+//! them. Function entries are taken from:
+//! - the entry point of the program
+//! - the function symbols of the ELF file (if the binary is not stripped)
+//! - the destinations of all function calls (`jal`/`jalr` with link), which is the
+//!   only source of information for stripped binaries (e.g. Selfie)
+//!
+//! The body of every function is computed with
+//! `cfg::compute_function_boundaries`. Every edge of the call graph is
+//! labeled with the `NodeIndex` of its call site.
+
+use crate::cfg::{
+    compute_function_boundaries, is_call, node_at, ControlFlowGraph, EdgeType, FunctionBoundary,
+};
+use crate::dot;
+use crate::elf::ElfMetadata;
+use petgraph::algo::tarjan_scc;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Graph;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// A function identified by its entry instruction
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct Function {
+    /// Symbol name or virtual address of the entry (stripped binaries)
+    pub name: String,
+    pub entry: NodeIndex,
+    pub boundary: FunctionBoundary,
+}
+
+/// Control flow graph of a single function. Callees are not part of it,
+/// instead a `Fallthrough` edge connects every call site with the instruction
+/// after it (if the callee returns).
+pub struct FunctionCfg {
+    pub graph: ControlFlowGraph,
+    /// `NodeIndex` of the entry instruction in `graph`
+    pub entry: NodeIndex,
+    /// `NodeIndex` of every instruction in the program `ControlFlowGraph`
+    pub instructions: Vec<NodeIndex>,
+}
+
+pub type CallGraphGraph = Graph<Function, NodeIndex>;
+
+/// Functions of a program and calls between them
+pub struct CallGraph {
+    pub graph: CallGraphGraph,
+    functions: BTreeMap<NodeIndex, NodeIndex>,
+}
+
+#[allow(dead_code)]
+impl CallGraph {
+    /// Identify all functions in `cfg` and the calls between them.
+    pub fn build(cfg: &ControlFlowGraph, elf_metadata: &ElfMetadata) -> Self {
+        let in_code = |address: u64| node_at(cfg, address);
+
+        let mut names = elf_metadata
+            .functions
+            .iter()
+            .filter_map(|symbol| in_code(symbol.address).map(|idx| (idx, symbol.name.clone())))
+            .collect::<HashMap<_, _>>();
+
+        let mut entries = names.keys().copied().collect::<BTreeSet<_>>();
+
+        entries.extend(in_code(elf_metadata.entry_address));
+
+        entries.extend(
+            cfg.edge_references()
+                .filter(|e| *e.weight() == EdgeType::Call)
+                .map(|e| e.target()),
+        );
+
+        let mut graph = CallGraphGraph::new();
+
+        let functions = compute_function_boundaries(cfg, &entries)
+            .into_iter()
+            .map(|(entry, boundary)| {
+                let name = names
+                    .remove(&entry)
+                    .unwrap_or_else(|| format!("{:#x}", cfg[entry].address));
+
+                let function = graph.add_node(Function {
+                    name,
+                    entry,
+                    boundary,
+                });
+
+                (entry, function)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let calls = graph
+            .node_indices()
+            .flat_map(|caller| {
+                graph[caller]
+                    .boundary
+                    .body
+                    .iter()
+                    .flat_map(|idx| {
+                        cfg.edges(*idx)
+                            .filter(|e| *e.weight() == EdgeType::Call)
+                            .map(|e| (caller, functions[&e.target()], e.source()))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        calls.into_iter().for_each(|(caller, callee, call_site)| {
+            graph.add_edge(caller, callee, call_site);
+        });
+
+        Self { graph, functions }
+    }
+
+    /// Get the function starting at instruction `entry`.
+    pub fn function_at_entry(&self, entry: NodeIndex) -> Option<NodeIndex> {
+        self.functions.get(&entry).copied()
+    }
+
+    /// Get the function starting at virtual `address`.
+    pub fn function_at_address(&self, cfg: &ControlFlowGraph, address: u64) -> Option<NodeIndex> {
+        node_at(cfg, address).and_then(|entry| self.function_at_entry(entry))
+    }
+
+    /// Get the function named `name`.
+    pub fn function_by_name(&self, name: &str) -> Option<NodeIndex> {
+        self.graph
+            .node_indices()
+            .find(|f| self.graph[*f].name == name)
+    }
+
+    /// Get all functions executing instruction `idx` (instructions can be
+    /// shared by multiple functions).
+    pub fn functions_containing(&self, idx: NodeIndex) -> Vec<NodeIndex> {
+        self.graph
+            .node_indices()
+            .filter(|f| self.graph[*f].boundary.body.contains(&idx))
+            .collect()
+    }
+
+    /// Get all functions calling `function`.
+    pub fn callers(&self, function: NodeIndex) -> Vec<NodeIndex> {
+        self.neighbors(function, petgraph::Incoming)
+    }
+
+    /// Get all functions called by `function`.
+    pub fn callees(&self, function: NodeIndex) -> Vec<NodeIndex> {
+        self.neighbors(function, petgraph::Outgoing)
+    }
+
+    fn neighbors(&self, function: NodeIndex, direction: petgraph::Direction) -> Vec<NodeIndex> {
+        self.graph
+            .neighbors_directed(function, direction)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Get all cycles of (mutually) recursive functions.
+    pub fn recursion_cycles(&self) -> Vec<Vec<NodeIndex>> {
+        tarjan_scc(&self.graph)
+            .into_iter()
+            .filter(|scc| scc.len() > 1 || self.graph.contains_edge(scc[0], scc[0]))
+            .collect()
+    }
+
+    /// Check if `function` is part of a recursion cycle.
+    pub fn is_recursive(&self, function: NodeIndex) -> bool {
+        self.recursion_cycles()
+            .iter()
+            .any(|cycle| cycle.contains(&function))
+    }
+
+    /// Extract the control flow graph of `function` from the program `cfg`.
+    pub fn function_cfg(&self, cfg: &ControlFlowGraph, function: NodeIndex) -> FunctionCfg {
+        let body = &self.graph[function].boundary.body;
+
+        let mut graph = ControlFlowGraph::new();
+
+        let local = body
+            .iter()
+            .map(|idx| (*idx, graph.add_node(cfg[*idx].clone())))
+            .collect::<HashMap<_, _>>();
+
+        body.iter().for_each(|idx| {
+            cfg.edges(*idx)
+                .filter(|e| !e.weight().is_stateful() && local.contains_key(&e.target()))
+                .for_each(|e| {
+                    graph.add_edge(local[idx], local[&e.target()], *e.weight());
+                });
+
+            if is_call(cfg[*idx].instruction) {
+                self.return_destinations(cfg, *idx)
+                    .iter()
+                    .filter_map(|dest| local.get(dest))
+                    .for_each(|dest| {
+                        graph.add_edge(local[idx], *dest, EdgeType::Fallthrough);
+                    });
+            }
+        });
+
+        FunctionCfg {
+            entry: local[&self.graph[function].entry],
+            graph,
+            instructions: body.iter().copied().collect(),
+        }
+    }
+
+    /// Get the instructions the callee returns to after the call at `call_site`.
+    fn return_destinations(&self, cfg: &ControlFlowGraph, call_site: NodeIndex) -> Vec<NodeIndex> {
+        cfg.edges(call_site)
+            .filter(|e| *e.weight() == EdgeType::Call)
+            .filter_map(|e| self.function_at_entry(e.target()))
+            .flat_map(|callee| self.graph[callee].boundary.return_sites.iter())
+            .flat_map(|return_site| {
+                cfg.edges(*return_site)
+                    .filter(|e| *e.weight() == EdgeType::Return(call_site))
+                    .map(|e| e.target())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Write the call graph of `cfg` to a dot file at `file` Path.
+pub fn write_to_file(
+    call_graph: &CallGraph,
+    cfg: &ControlFlowGraph,
+    file: &Path,
+) -> Result<(), std::io::Error> {
+    dot::write_graph(
+        &call_graph.graph,
+        |function| format!("{}\n", call_graph.graph[function].name),
+        |call_site| {
+            (
+                format!("{:#x}", cfg[*call_site].address),
+                dot::edge_style(EdgeType::Call),
+            )
+        },
+        |_| None,
+        &BTreeSet::new(),
+        file,
+    )
+}
+
+/// Write the control flow graph of a single function called `name` to a dot
+/// file at `file` Path.
+pub fn write_function_to_file(
+    function: &FunctionCfg,
+    name: &str,
+    file: &Path,
+) -> Result<(), std::io::Error> {
+    dot::write_graph(
+        &function.graph,
+        |idx| format!("{:?}\n", function.graph[idx]),
+        dot::edge_label,
+        |_| Some((0, String::from(name))),
+        &BTreeSet::new(),
+        file,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+    use crate::elf::Symbol;
+
+    fn elf_metadata(functions: Vec<Symbol>) -> ElfMetadata {
+        ElfMetadata {
+            entry_address: 0,
+            code_length: 0,
+            code_start: 0,
+            functions,
+        }
+    }
+
+    // main calls f twice, f calls itself
+    fn recursive_program() -> ControlFlowGraph {
+        cfg::build(
+            assemble(&[
+                0x010000ef, // jal ra, 16
+                0x00c000ef, // jal ra, 12
+                0x05d00893, // addi a7, zero, 93
+                0x00000073, // ecall
+                0x00050663, // beq a0, zero, 12
+                0xffdff0ef, // jal ra, -4
+                0x00008067, // jalr zero, 0(ra)
+                0x00008067, // jalr zero, 0(ra)
+            ])
+            .as_slice(),
+            0,
+        )
+    }
+
+    #[test]
+    fn can_identify_functions_of_stripped_binaries() {
+        let cfg = recursive_program();
+        let call_graph = CallGraph::build(&cfg, &elf_metadata(vec![]));
+
+        assert_eq!(call_graph.graph.node_count(), 2);
+
+        let main = call_graph.function_at_entry(NodeIndex::new(0)).unwrap();
+        let f = call_graph.function_at_entry(NodeIndex::new(4)).unwrap();
+
+        assert_eq!(call_graph.graph[f].name, "0x10");
+        assert_eq!(call_graph.callees(main), vec![f]);
+        assert_eq!(call_graph.callers(f), vec![main, f]);
+        assert_eq!(call_graph.graph.edges(main).count(), 2, "two call sites");
+        assert!(call_graph.is_recursive(f));
+        assert!(!call_graph.is_recursive(main));
+        assert_eq!(call_graph.functions_containing(NodeIndex::new(6)), vec![f]);
+    }
+
+    #[test]
+    fn can_name_functions_with_symbols() {
+        let cfg = recursive_program();
+        let call_graph = CallGraph::build(
+            &cfg,
+            &elf_metadata(vec![Symbol {
+                name: String::from("f"),
+                address: 16,
+                size: 16,
+            }]),
+        );
+
+        let f = call_graph.function_by_name("f").unwrap();
+
+        assert_eq!(call_graph.graph[f].entry, NodeIndex::new(4));
+    }
+
+    #[test]
+    fn can_extract_function_cfg() {
+        let cfg = recursive_program();
+        let call_graph = CallGraph::build(&cfg, &elf_metadata(vec![]));
+
+        let main = call_graph.function_at_entry(NodeIndex::new(0)).unwrap();
+        let main_cfg = call_graph.function_cfg(&cfg, main);
+
+        assert_eq!(main_cfg.graph.node_count(), 4);
+        assert_eq!(
+            main_cfg
+                .graph
+                .edge_references()
+                .filter(|e| *e.weight() == EdgeType::Fallthrough)
+                .count(),
+            3,
+            "calls are summarized"
+        );
+        assert!(main_cfg
+            .graph
+            .edge_references()
+            .all(|e| !e.weight().is_stateful()));
+
+        let f = call_graph.function_at_entry(NodeIndex::new(4)).unwrap();
+        let f_cfg = call_graph.function_cfg(&cfg, f);
+
+        assert_eq!(
+            f_cfg.instructions,
+            (4..8).map(NodeIndex::new).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,134 +1,292 @@
-use crate::cfg::ControlFlowGraph;
+use crate::cfg::{is_return, node_at, ControlFlowGraph, INSTRUCTION_SIZE};
+use crate::elf::ElfMetadata;
+use crate::formula_graph::SyscallId;
+use crate::value_analysis::{
+    jump_targets, memory_access, propagate_values, AbstractState, ValueSet, INITIAL_STACK_POINTER,
+};
 use petgraph::graph::NodeIndex;
 use petgraph::visit::EdgeRef;
 use riscv_decode::Instruction;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Range;
 
-pub struct CandidatePath<'a> {
+const REG_A1: u32 = 11;
+const REG_A2: u32 = 12;
+
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct CandidatePath {
     pub root: NodeIndex, // instruction we want to evaluate (exit syscall, division with a potential 0 as divisor, ... )
+    pub class: BugClass, // kind of bug which may occur at the root
     pub path: ControlFlowGraph, // actual candidate path from the root to instuctions with no incoming edges and read syscalls
-    pub alternative_roots: Vec<NodeIndex>, // alternative roots for generating new candidate paths from the same control-flow graph (relevant for CandidatePath::next())
-    pub cfg: &'a ControlFlowGraph, // control-flow graph which this candidate path is extracted from
+    pub instructions: Vec<NodeIndex>, // NodeIndex in cfg of every node in path (indexed by the NodeIndex in path)
 }
 
 #[allow(dead_code)]
-impl<'a> CandidatePath<'a> {
-    // may return a reference to a candidate path with a new path and root field
-    pub fn next(&'a mut self) -> Option<&mut CandidatePath> {
-        if let Some(root) = self.alternative_roots.pop() {
-            self.root = root;
-            self.path = ControlFlowGraph::new();
-            self.compute_candidate_path(self.root);
-            Some(self)
-        } else {
-            None
-        }
-    }
+impl CandidatePath {
+    // computes the candidate path of a bug target in the provided cfg;
+    // the path is the backward slice of the root: all nodes of the cfg which can reach the root,
+    // together with all incoming edges of the nodes, which have been explored backwards,
+    // every node is explored at most once (terminates for loops),
+    // the end nodes of the path are read syscalls (which are not explored) or nodes with no incoming neighbors
+    pub fn new(cfg: &ControlFlowGraph, target: BugTarget) -> Self {
+        let mut slice = BTreeSet::new();
+        let mut explored = vec![];
+        let mut worklist = vec![target.node];
 
-    // computes the candidate path using its cfg and a provided node, it populates its path field;
-    // to begin with, this function always gets invoked with a root node as parameter,
-    // it adds all "incoming neighbor nodes" (neighbors which are connected with an incoming edge) together with respective incoming edges to the path,
-    // then recusively calls itself with those neighboring nodes as parameter,
-    // thus propagating through the cfg, continuing to add neighboring nodes and edges
-    // the end nodes of the path are read syscalls or nodes with no incoming neighbors
-    fn compute_candidate_path(&mut self, node: NodeIndex) {
-        self.cfg
-            .neighbors_directed(node, petgraph::Incoming)
-            .for_each(|x| {
-                self.path.node_indices().for_each(|idx| {
-                    if self.cfg[x] == self.path[idx] {
-                        // node is already in path
-                    } else {
-                        self.path.add_node(self.cfg[x]);
+        slice.insert(target.node);
+
+        while let Some(idx) = worklist.pop() {
+            if idx != target.node && is_read(cfg, idx) {
+                // stop candidate path generation at read syscalls
+                continue;
+            }
+
+            explored.push(idx);
+
+            cfg.neighbors_directed(idx, petgraph::Incoming)
+                .for_each(|x| {
+                    if slice.insert(x) {
+                        worklist.push(x);
                     }
                 });
-                self.path.add_edge(x, node, None);
-                match self.cfg[x] {
-                    Instruction::Ecall => {
-                        if is_read(self.cfg, x) {
-                            // stop candidate path generation at read syscalls
-                        } else {
-                            self.compute_candidate_path(x);
-                        }
-                    }
-                    _ => {
-                        self.compute_candidate_path(x);
-                    }
-                }
+        }
+
+        // nodes are added in the order of the cfg (sorted by address)
+        let mut path = ControlFlowGraph::new();
+        let instructions = slice.into_iter().collect::<Vec<_>>();
+
+        let local = instructions
+            .iter()
+            .map(|idx| (*idx, path.add_node(cfg[*idx].clone())))
+            .collect::<HashMap<_, _>>();
+
+        explored.iter().for_each(|idx| {
+            cfg.edges_directed(*idx, petgraph::Incoming).for_each(|e| {
+                path.add_edge(local[&e.source()], local[idx], *e.weight());
             })
+        });
+
+        Self {
+            root: target.node,
+            class: target.class,
+            path,
+            instructions,
+        }
     }
+}
 
-    // invokes find_roots() which populates the alternative_roots field of the new candidate path
-    // invokes compute_candidate_path() to populate its path field
-    pub fn generate_candidate_path(graph: &ControlFlowGraph) -> Option<CandidatePath> {
-        // find root nodes (exit syscall, division with a potential 0 as divisor) using a provided control-flow graph
-        fn find_roots(graph: &ControlFlowGraph) -> Option<Vec<NodeIndex>> {
-            let mut roots = vec![];
-
-            graph.node_indices().for_each(|idx| {
-                if let Instruction::Ecall = graph[idx] {
-                    if let Some(idx) = is_exit_point(graph, idx) {
-                        roots.push(idx);
-                    }
-                } else if let Instruction::Divu(_rtype) = graph[idx] {
-                    if let Some(idx) = is_exit_point(graph, idx) {
-                        roots.push(idx);
-                    }
-                };
-            });
+// lazy iterator over the candidate paths of all bug targets of a cfg (in the order of find_bug_targets());
+// bug targets are computed up front, every candidate path is computed when it is requested
+// and owned by the caller (candidate paths can be filtered, collected or sent to other threads)
+#[allow(dead_code)]
+pub struct CandidatePaths<'a> {
+    cfg: &'a ControlFlowGraph,
+    targets: std::vec::IntoIter<BugTarget>,
+}
 
-            if roots.is_empty() {
-                None
-            } else {
-                Some(roots)
-            }
-        }
+impl<'a> Iterator for CandidatePaths<'a> {
+    type Item = CandidatePath;
 
-        if let Some(mut alternative_roots) = find_roots(&graph) {
-            if let Some(root) = alternative_roots.pop() {
-                let mut candidate_path = CandidatePath {
-                    cfg: graph,
-                    path: ControlFlowGraph::new(),
-                    root,
-                    alternative_roots,
-                };
-                candidate_path.compute_candidate_path(candidate_path.root);
-                Some(candidate_path)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+    fn next(&mut self) -> Option<CandidatePath> {
+        self.targets
+            .next()
+            .map(|target| CandidatePath::new(self.cfg, target))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.targets.size_hint()
     }
 }
 
-// checks if an instruction is a read syscall
+// generates the candidate paths of all bug targets of a provided control-flow graph
 #[allow(dead_code)]
-fn is_read(graph: &ControlFlowGraph, idx: NodeIndex) -> bool {
-    match graph[NodeIndex::new(idx.index() - 1)] {
-        Instruction::Addi(a) => a.imm() == 63,
-        _ => false,
+pub fn candidate_paths<'a>(
+    graph: &'a ControlFlowGraph,
+    elf_metadata: &ElfMetadata,
+) -> CandidatePaths<'a> {
+    CandidatePaths {
+        cfg: graph,
+        targets: find_bug_targets(graph, elf_metadata).into_iter(),
     }
 }
 
-// checks if an instruction is either a divu or an exit syscall
+// kind of bug, which may occur at a root;
+// read syscalls, which overflow a buffer into neighbouring data, are not detected:
+// the value-set analysis knows neither the bounds of stack frames nor of the heap
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum BugClass {
+    ExitCode,              // exit syscall (with a potentially non-zero exit code)
+    DivisionByZero,        // divu with a potential 0 as divisor
+    RemainderByZero,       // remu with a potential 0 as divisor
+    InvalidMemoryAccess, // load or store below the code segment or above the initial stack pointer
+    UnalignedMemoryAccess, // load or store to an address, which is not aligned to the access size
+    CodeSegmentWrite,    // store into the code segment
+    InvalidJumpTarget,   // jalr outside of the code segment
+    CodeSegmentRead,     // read syscall into the code segment
+}
+
+impl fmt::Display for BugClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BugClass::ExitCode => "exit code",
+            BugClass::DivisionByZero => "division by zero",
+            BugClass::RemainderByZero => "remainder by zero",
+            BugClass::InvalidMemoryAccess => "invalid memory access",
+            BugClass::UnalignedMemoryAccess => "unaligned memory access",
+            BugClass::CodeSegmentWrite => "write into code segment",
+            BugClass::InvalidJumpTarget => "invalid jump target",
+            BugClass::CodeSegmentRead => "read into code segment",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+// instruction of a cfg, where a bug of a certain class may occur
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BugTarget {
+    pub node: NodeIndex,
+    pub class: BugClass,
+}
+
+// find all bug targets of a provided control-flow graph (ordered by node and bug class);
+// the possible values of registers are computed with a value-set analysis from the entry of the elf file,
+// instructions which are not reachable from the entry are skipped
+pub fn find_bug_targets(graph: &ControlFlowGraph, elf_metadata: &ElfMetadata) -> Vec<BugTarget> {
+    let states = match node_at(graph, elf_metadata.entry_address) {
+        Some(entry) => propagate_values(graph, entry),
+        None => return vec![],
+    };
+
+    let code_segment = elf_metadata.code_start..elf_metadata.code_start + elf_metadata.code_length;
+
+    graph
+        .node_indices()
+        .filter_map(|idx| states.get(&idx).map(|state| (idx, state)))
+        .flat_map(|(idx, state)| {
+            bug_classes(graph, idx, state, &code_segment)
+                .into_iter()
+                .map(move |class| BugTarget { node: idx, class })
+        })
+        .collect()
+}
+
+// find root nodes (nodes of all bug targets) using a provided control-flow graph
+pub fn find_roots(graph: &ControlFlowGraph, elf_metadata: &ElfMetadata) -> Vec<NodeIndex> {
+    let mut roots = find_bug_targets(graph, elf_metadata)
+        .into_iter()
+        .map(|target| target.node)
+        .collect::<Vec<_>>();
+
+    roots.dedup();
+
+    roots
+}
+
+// checks if an instruction is a read syscall
 #[allow(dead_code)]
-fn is_exit_point(graph: &ControlFlowGraph, idx: NodeIndex) -> Option<NodeIndex> {
-    match graph[idx] {
+fn is_read(graph: &ControlFlowGraph, idx: NodeIndex) -> bool {
+    graph[idx].may_be_syscall(SyscallId::Read)
+}
+
+// checks which bugs may occur at an instruction in the provided state (before the instruction)
+fn bug_classes(
+    graph: &ControlFlowGraph,
+    idx: NodeIndex,
+    state: &AbstractState,
+    code_segment: &Range<u64>,
+) -> BTreeSet<BugClass> {
+    // checks if any value of a set satisfies the predicate (unknown for top)
+    let check = |values: &ValueSet, predicate: &dyn Fn(u64) -> bool| {
+        values
+            .values()
+            .map(|values| values.iter().any(|v| predicate(*v)))
+    };
+
+    // top is no evidence for a bug, except for divisors: they are typically derived from the input,
+    // which might be zero (decided by the formula of a path)
+    let may =
+        |values: &ValueSet, predicate: &dyn Fn(u64) -> bool| check(values, predicate) == Some(true);
+    let may_be_zero = |values: &ValueSet| check(values, &|v| v == 0) != Some(false);
+
+    let mut classes = BTreeSet::new();
+    let instruction = graph[idx].instruction;
+
+    match instruction {
         // get division exit points
-        Instruction::Divu(_rtype) => Some(idx),
-        _ => match graph[NodeIndex::new(idx.index() - 1)] {
+        Instruction::Divu(r) if may_be_zero(state.register(r.rs2())) => {
+            classes.insert(BugClass::DivisionByZero);
+        }
+        Instruction::Remu(r) if may_be_zero(state.register(r.rs2())) => {
+            classes.insert(BugClass::RemainderByZero);
+        }
+        Instruction::Ecall => {
             // get exit syscall exit points
-            Instruction::Addi(a) => {
-                if a.imm() == 93 {
-                    Some(idx)
-                } else {
-                    None
+            if graph[idx].may_be_syscall(SyscallId::Exit) {
+                classes.insert(BugClass::ExitCode);
+            }
+            if graph[idx].may_be_syscall(SyscallId::Read) {
+                let buffer = state.register(REG_A1);
+                let size = state.register(REG_A2);
+
+                let overlaps = match (buffer.values(), size.values()) {
+                    (Some(buffers), Some(sizes)) => buffers.iter().any(|b| {
+                        sizes.iter().any(|s| match b.checked_add(*s) {
+                            Some(end) => {
+                                *s > 0 && *b < code_segment.end && code_segment.start < end
+                            }
+                            None => true,
+                        })
+                    }),
+                    _ => false,
+                };
+
+                if overlaps {
+                    classes.insert(BugClass::CodeSegmentRead);
                 }
             }
-            _ => None,
-        },
+        }
+        Instruction::Jalr(_) if !is_return(instruction) => {
+            let targets = jump_targets(state, instruction).unwrap();
+
+            if may(&targets, &|t| {
+                !code_segment.contains(&t) || t % INSTRUCTION_SIZE != 0
+            }) {
+                classes.insert(BugClass::InvalidJumpTarget);
+            }
+        }
+        _ => {
+            if let Some((addresses, size)) = memory_access(state, instruction) {
+                // the program break is unknown, hence the stack bounds the accessible memory
+                if may(&addresses, &|a| {
+                    a < code_segment.start
+                        || a.checked_add(size)
+                            .filter(|end| *end <= INITIAL_STACK_POINTER)
+                            .is_none()
+                }) {
+                    classes.insert(BugClass::InvalidMemoryAccess);
+                }
+                if may(&addresses, &|a| a % size != 0) {
+                    classes.insert(BugClass::UnalignedMemoryAccess);
+                }
+                if is_store(instruction) && may(&addresses, &|a| code_segment.contains(&a)) {
+                    classes.insert(BugClass::CodeSegmentWrite);
+                }
+            }
+        }
     }
+
+    classes
+}
+
+// checks if an instruction writes to memory
+fn is_store(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Sd(_) | Instruction::Sw(_) | Instruction::Sh(_) | Instruction::Sb(_)
+    )
 }
 
 // extracts a trivial condidate path starting from the very last jump to an ecall (exit)
@@ -149,5 +307,110 @@ pub fn extract_trivial_candidate_path(graph: &ControlFlowGraph) -> Vec<Instructi
         path.push(n);
         idx = n;
     }
-    path.iter().map(|idx| graph[*idx]).collect()
+    path.iter().map(|idx| graph[*idx].instruction).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+
+    fn elf_metadata(code_start: u64, code: &[u8]) -> ElfMetadata {
+        ElfMetadata {
+            entry_address: code_start,
+            code_length: code.len() as u64,
+            code_start,
+            functions: vec![],
+        }
+    }
+
+    #[test]
+    fn can_slice_loops_backwards_up_to_read_syscalls() {
+        let code = assemble(&[
+            0x00000013, // nop
+            0x03f00893, // addi a7, zero, 63
+            0x00000073, // ecall (read of unknown buffer)
+            0xfff50513, // loop: addi a0, a0, -1
+            0x00050463, // beq a0, zero, 8
+            0xff9ff06f, // jal zero, loop
+            0x02b55533, // divu a0, a0, a1
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall (exit)
+        ]);
+        let graph = cfg::build(code.as_slice(), 0);
+        let elf_metadata = elf_metadata(0, code.as_slice());
+
+        assert_eq!(
+            candidate_paths(&graph, &elf_metadata)
+                .map(|c| (c.root.index(), c.class))
+                .collect::<Vec<_>>(),
+            vec![(6, BugClass::DivisionByZero), (8, BugClass::ExitCode)]
+        );
+
+        let candidate_path = candidate_paths(&graph, &elf_metadata)
+            .find(|c| c.class == BugClass::ExitCode)
+            .unwrap();
+
+        assert_eq!(candidate_path.root, NodeIndex::new(8));
+        assert_eq!(
+            candidate_path.instructions,
+            (2..9).map(NodeIndex::new).collect::<Vec<_>>(),
+            "nodes before the read syscall are not part of the path"
+        );
+        assert_eq!(candidate_path.path.edge_count(), 7);
+
+        candidate_path.path.edge_references().for_each(|e| {
+            let source = candidate_path.instructions[e.source().index()];
+            let target = candidate_path.instructions[e.target().index()];
+
+            assert_eq!(candidate_path.path[e.source()], graph[source]);
+            assert!(graph.find_edge(source, target).is_some());
+        });
+
+        let candidate_path = candidate_paths(&graph, &elf_metadata).next().unwrap();
+
+        assert_eq!(candidate_path.root, NodeIndex::new(6));
+        assert_eq!(candidate_path.path.node_count(), 5);
+        assert_eq!(candidate_path.path.edge_count(), 5);
+    }
+
+    #[test]
+    fn can_classify_bug_targets() {
+        let code = assemble(&[
+            0x02b57533, // remu a0, a0, a1 (unknown divisor)
+            0x00003283, // ld t0, 0(zero)
+            0xff803283, // ld t0, -8(zero)
+            0xff413303, // ld t1, -12(sp)
+            0x00053303, // ld t1, 0(a0) (unknown address)
+            0x00000397, // auipc t2, 0
+            0xfe03be23, // sd zero, -4(t2)
+            0x00038593, // addi a1, t2, 0
+            0x00800613, // addi a2, zero, 8
+            0x03f00893, // addi a7, zero, 63
+            0x00000073, // ecall (read)
+            0x00000067, // jalr zero, 0(zero)
+            0x02b55533, // divu a0, a0, a1 (unreachable)
+        ]);
+        let graph = cfg::build(code.as_slice(), 0x10000);
+        let elf_metadata = elf_metadata(0x10000, code.as_slice());
+
+        let targets = find_bug_targets(&graph, &elf_metadata)
+            .into_iter()
+            .map(|target| (target.node.index(), target.class))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            targets,
+            vec![
+                (0, BugClass::RemainderByZero),
+                (1, BugClass::InvalidMemoryAccess),
+                (2, BugClass::InvalidMemoryAccess),
+                (3, BugClass::UnalignedMemoryAccess),
+                (6, BugClass::CodeSegmentWrite),
+                (10, BugClass::CodeSegmentRead),
+                (11, BugClass::InvalidJumpTarget),
+            ]
+        );
+        assert_eq!(find_roots(&graph, &elf_metadata).len(), 7);
+    }
 }
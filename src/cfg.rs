@@ -2,34 +2,159 @@
 //!
 //! This module defines and handles control flow graphs.
 //!
-//! There are three different kind of edges:
+//! Instructions are discovered with a linear sweep over the whole code
+//! segment or with a recursive traversal starting at the entry points (see
+//! `Strategy`). Every node carries its virtual address and nodes are always
+//! sorted by their address.
+//!
+//! There are three different kind of edges (see `EdgeType`):
 //! - trivial edges (`pc = pc + 4;`)
-//!   - any non control flow instruction
-//!   - `beq`: false edge
+//!   - any non control flow instruction (`Fallthrough`)
+//!   - `beq`: false edge (`BranchNotTaken`)
+//!   - `ecall`: when the syscall returns (`SyscallExit`)
 //! - pure edges
-//!   - `beq`: true edge
-//!   - `jal`: when link not used (=> `rd` is zero)
+//!   - `beq`: true edge (`BranchTaken`)
+//!   - `jal`: when link not used (=> `rd` is zero) (`Jump`)
+//!   - `jalr`: indirect jump (not a return) when link not used (`Jump`)
 //! - stateful edges
-//!   - `jal`: when link is used (=> `rd` is `ra`)
-//!   - `jalr`
+//!   - `jal`/`jalr`: when link is used (=> `rd` is `ra`) (`Call`)
+//!   - `jalr zero, 0(ra)` (`Return` to the instruction after the call site)
+//!
+//! The targets of indirect jumps and calls (`jalr` except returns) are
+//! resolved with a value-set analysis (see `value_analysis`). Indirect jumps,
+//! which can not be resolved, have no outgoing edge and are reported by
+//! `find_unresolved_indirect_jumps`.
+//!
+//! The same analysis computes the possible values of `a7` at every `ecall`,
+//! which are stored in its `Node` as syscall numbers. An `ecall`, which is
+//! always an exit syscall, has no outgoing edge.
 
-use crate::elf::{load_file, ElfMetadata};
+use crate::cache;
+use crate::disassemble::disassemble_instruction;
+use crate::elf::{load, ElfMetadata};
+use crate::formula_graph::SyscallId;
+use crate::value_analysis::{propagate_values, resolve_indirect_jumps, ValueSet};
 use byteorder::{ByteOrder, LittleEndian};
-use petgraph::dot::Dot;
-use petgraph::graph::{EdgeIndex, NodeIndex};
+use core::fmt;
+use petgraph::graph::NodeIndex;
 use petgraph::visit::EdgeRef;
 use petgraph::Graph;
 use riscv_decode::decode;
 use riscv_decode::Instruction;
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
 use std::path::Path;
-use std::process::Command;
 use std::vec::Vec;
 
-type Edge = (NodeIndex, NodeIndex, Option<NodeIndex>);
-pub type ControlFlowGraph = Graph<Instruction, Option<NodeIndex>>;
+/// Kind of control flow between two instructions
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EdgeType {
+    /// Any non control flow instruction continues with the next instruction.
+    Fallthrough,
+    /// `beq` jumps to its destination, because the condition holds.
+    BranchTaken,
+    /// `beq` continues with the next instruction, because the condition does not hold.
+    BranchNotTaken,
+    /// `jal` (or an indirect `jalr`) without link jumps to its destination.
+    Jump,
+    /// `jal` (or an indirect `jalr`) with link calls the function at its destination.
+    Call,
+    /// `jalr` returns from a function to the instruction after the call site.
+    Return(NodeIndex),
+    /// `ecall` continues with the next instruction after the syscall returns.
+    SyscallExit,
+}
+
+#[allow(dead_code)]
+impl EdgeType {
+    /// Check if the edge is a trivial edge (`pc = pc + 4;`).
+    pub fn is_trivial(self) -> bool {
+        matches!(
+            self,
+            EdgeType::Fallthrough | EdgeType::BranchNotTaken | EdgeType::SyscallExit
+        )
+    }
+
+    /// Check if the edge is a pure edge (no state except of `pc` involved).
+    pub fn is_pure(self) -> bool {
+        matches!(self, EdgeType::BranchTaken | EdgeType::Jump)
+    }
+
+    /// Check if the edge is a stateful edge (function call or return).
+    pub fn is_stateful(self) -> bool {
+        matches!(self, EdgeType::Call | EdgeType::Return(_))
+    }
+
+    /// Get the branch decision (`true` if the branch is taken) of a `beq` edge.
+    pub fn branch_decision(self) -> Option<bool> {
+        match self {
+            EdgeType::BranchTaken => Some(true),
+            EdgeType::BranchNotTaken => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Instruction of a `ControlFlowGraph`
+#[derive(Clone, PartialEq)]
+pub struct Node {
+    /// Virtual address of the instruction
+    pub address: u64,
+    pub instruction: Instruction,
+    /// Possible syscall numbers (value of `a7`) of an `ecall`
+    pub syscall: Option<ValueSet>,
+}
+
+#[allow(dead_code)]
+impl Node {
+    pub fn new(address: u64, instruction: Instruction) -> Self {
+        Self {
+            address,
+            instruction,
+            syscall: None,
+        }
+    }
+
+    /// Check if the node is a syscall, which is always `id`.
+    pub fn is_syscall(&self, id: SyscallId) -> bool {
+        match &self.syscall {
+            Some(syscall) => syscall.as_constant() == Some(id as u64),
+            None => false,
+        }
+    }
+
+    /// Check if the node is a syscall, which might be `id`. Syscalls with an
+    /// unknown number (`ValueSet::Top`) are not considered.
+    pub fn may_be_syscall(&self, id: SyscallId) -> bool {
+        match &self.syscall {
+            Some(ValueSet::Values(values)) => values.contains(&(id as u64)),
+            Some(ValueSet::Top) | None => false,
+        }
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x}: {}",
+            self.address,
+            disassemble_instruction(self.instruction)
+        )?;
+
+        match &self.syscall {
+            Some(ValueSet::Values(values)) => write!(f, " {:?}", values),
+            Some(ValueSet::Top) => write!(f, " {{?}}"),
+            None => Ok(()),
+        }
+    }
+}
+
+type Edge = (NodeIndex, NodeIndex, EdgeType);
+pub type ControlFlowGraph = Graph<Node, EdgeType>;
+
+/// Size of a RISC-U instruction in bytes
+pub const INSTRUCTION_SIZE: u64 = 4;
 
 /// Extend sign
 pub fn sign_extend(n: u32, b: u32) -> u32 {
@@ -42,47 +167,174 @@ pub fn sign_extend(n: u32, b: u32) -> u32 {
     }
 }
 
-/// Get `NodeIndex` of `beq` destination.
-fn calculate_beq_destination(idx: NodeIndex, imm: u32) -> NodeIndex {
-    NodeIndex::new(sign_extend(imm / 4, 11).wrapping_add(idx.index() as u32) as usize)
+/// Get address of `beq` destination.
+fn calculate_beq_destination(address: u64, imm: u32) -> u64 {
+    address.wrapping_add(sign_extend(imm, 13) as i32 as u64)
+}
+
+/// Get address of `jal` destination.
+fn calculate_jal_destination(address: u64, imm: u32) -> u64 {
+    address.wrapping_add(sign_extend(imm, 21) as i32 as u64)
 }
 
-/// Get `NodeIndex` of `jal` destination.
-fn calculate_jal_destination(idx: NodeIndex, imm: u32) -> NodeIndex {
-    NodeIndex::new(sign_extend(imm / 4, 19).wrapping_add(idx.index() as u32) as usize)
+/// Get the `NodeIndex` of the instruction at virtual `address`.
+/// Nodes are always sorted by their address, hence they are an index of
+/// themselves.
+pub fn node_at(graph: &ControlFlowGraph, address: u64) -> Option<NodeIndex> {
+    graph
+        .raw_nodes()
+        .binary_search_by_key(&address, |node| node.weight.address)
+        .ok()
+        .map(NodeIndex::new)
 }
 
-/// Create a `ControlFlowGraph` from an `u8` slice without fixing edges
-fn create_instruction_graph(binary: &[u8]) -> ControlFlowGraph {
+/// Get the `NodeIndex` of the instruction right after `idx` (if there is one).
+pub fn next_node(graph: &ControlFlowGraph, idx: NodeIndex) -> Option<NodeIndex> {
+    let next = NodeIndex::new(idx.index() + 1);
+
+    graph
+        .node_weight(next)
+        .filter(|node| node.address == graph[idx].address + INSTRUCTION_SIZE)
+        .map(|_| next)
+}
+
+/// Register holding the return address (`ra`)
+const REG_RA: u32 = 1;
+/// Register holding the syscall number (`a7`)
+const REG_A7: u32 = 17;
+
+/// Check if `instruction` returns from a function (`jalr zero, 0(ra)`).
+pub fn is_return(instruction: Instruction) -> bool {
+    match instruction {
+        Instruction::Jalr(i) => i.rd() == 0 && i.rs1() == REG_RA,
+        _ => false,
+    }
+}
+
+/// Check if `instruction` calls a function (`jal` or `jalr` with link).
+pub fn is_call(instruction: Instruction) -> bool {
+    match instruction {
+        Instruction::Jal(j) => j.rd() != 0,
+        Instruction::Jalr(i) => i.rd() != 0,
+        _ => false,
+    }
+}
+
+/// Strategy to discover the instructions of a code segment
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    /// Decode every word of the code segment (fails on embedded data).
+    LinearSweep,
+    /// Decode only instructions reachable from the entry points.
+    RecursiveTraversal,
+}
+
+/// Decode every word of `binary`, which is loaded at `code_start`.
+fn linear_sweep(binary: &[u8], code_start: u64) -> Result<BTreeMap<u64, Instruction>, String> {
     binary
         .chunks_exact(4)
         .map(LittleEndian::read_u32)
-        .map(decode)
-        .map(Result::unwrap)
-        .fold(ControlFlowGraph::new(), |mut g, i| {
-            g.add_node(i);
+        .enumerate()
+        .map(|(i, word)| {
+            let address = code_start + i as u64 * INSTRUCTION_SIZE;
+
+            decode(word)
+                .map(|instruction| (address, instruction))
+                .map_err(|_| {
+                    format!(
+                        "cannot decode word {:#010x} at address {:#x} (try the recursive-traversal strategy)",
+                        word, address
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Get the addresses control might flow to after `instruction` at `address`.
+/// Calls are assumed to return and indirect jumps are resolved later on.
+fn static_successors(address: u64, instruction: Instruction) -> Vec<u64> {
+    let next = address + INSTRUCTION_SIZE;
+
+    match instruction {
+        Instruction::Jal(j) if j.rd() == 0 => vec![calculate_jal_destination(address, j.imm())],
+        Instruction::Jal(j) => vec![calculate_jal_destination(address, j.imm()), next],
+        Instruction::Jalr(i) if i.rd() == 0 => vec![],
+        Instruction::Beq(b) => vec![calculate_beq_destination(address, b.imm()), next],
+        _ => vec![next],
+    }
+}
+
+/// Decode all instructions of `binary` (loaded at `code_start`), which are
+/// reachable from `entries`. Words, which can not be decoded, are treated as
+/// data.
+fn recursive_traversal(
+    binary: &[u8],
+    code_start: u64,
+    entries: &BTreeSet<u64>,
+) -> BTreeMap<u64, Instruction> {
+    let mut instructions = BTreeMap::new();
+    let mut worklist = entries.iter().copied().collect::<Vec<_>>();
+
+    while let Some(address) = worklist.pop() {
+        if instructions.contains_key(&address) || address % INSTRUCTION_SIZE != 0 {
+            continue;
+        }
+
+        let offset = match address.checked_sub(code_start) {
+            Some(offset) if offset + INSTRUCTION_SIZE <= binary.len() as u64 => offset as usize,
+            _ => continue,
+        };
+
+        if let Ok(instruction) = decode(LittleEndian::read_u32(&binary[offset..])) {
+            instructions.insert(address, instruction);
+            worklist.extend(static_successors(address, instruction));
+        }
+    }
+
+    instructions
+}
+
+/// Create a `ControlFlowGraph` from decoded instructions without fixing edges
+fn create_instruction_graph(instructions: &BTreeMap<u64, Instruction>) -> ControlFlowGraph {
+    instructions
+        .iter()
+        .fold(ControlFlowGraph::new(), |mut g, (address, i)| {
+            g.add_node(Node::new(*address, *i));
             g
         })
 }
 
 /// Compute trivial edges
 fn construct_edge_if_trivial(graph: &ControlFlowGraph, idx: NodeIndex) -> Option<Edge> {
-    match graph[idx] {
+    let next_idx = next_node(graph, idx)?;
+
+    match graph[idx].instruction {
         Instruction::Jal(_) | Instruction::Jalr(_) => None,
-        _ if idx.index() + 1 < graph.node_count() => {
-            Some((idx, NodeIndex::new(idx.index() + 1), None))
-        }
-        _ => None,
+        Instruction::Beq(_) => Some((idx, next_idx, EdgeType::BranchNotTaken)),
+        Instruction::Ecall => Some((idx, next_idx, EdgeType::SyscallExit)),
+        _ => Some((idx, next_idx, EdgeType::Fallthrough)),
     }
 }
 
 /// Compute pure edges
 fn construct_edge_if_pure(graph: &ControlFlowGraph, idx: NodeIndex) -> Option<Edge> {
-    match graph[idx] {
-        Instruction::Jal(i) if i.rd() == 0 => {
-            Some((idx, calculate_jal_destination(idx, i.imm()), None))
-        }
-        Instruction::Beq(i) => Some((idx, calculate_beq_destination(idx, i.imm()), None)),
+    match graph[idx].instruction {
+        Instruction::Jal(i) if i.rd() == 0 => Some((
+            idx,
+            node_at(
+                graph,
+                calculate_jal_destination(graph[idx].address, i.imm()),
+            )?,
+            EdgeType::Jump,
+        )),
+        Instruction::Beq(i) => Some((
+            idx,
+            node_at(
+                graph,
+                calculate_beq_destination(graph[idx].address, i.imm()),
+            )?,
+            EdgeType::BranchTaken,
+        )),
         _ => None,
     }
 }
@@ -98,87 +350,198 @@ where
         .collect::<Vec<Edge>>()
 }
 
-/// Compute all return locations in a given function starting at idx.
-fn compute_return_edge_position(graph: &ControlFlowGraph, idx: NodeIndex) -> HashSet<NodeIndex> {
-    match graph[idx] {
-        Instruction::Jalr(_) => {
-            let mut set = HashSet::new();
-            set.insert(idx);
-            set
+/// Body and return sites of a function
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FunctionBoundary {
+    /// All instructions executed by the function itself (without callees)
+    pub body: BTreeSet<NodeIndex>,
+    /// All `jalr` instructions returning from the function
+    pub return_sites: BTreeSet<NodeIndex>,
+}
+
+/// Explore the function starting at `entry` with a worklist.
+/// Calls are stepped over to the instruction after the call site, but only if
+/// a callee is known to return (according to `boundaries`). Unresolved
+/// indirect calls are assumed to return.
+fn explore_function(
+    graph: &ControlFlowGraph,
+    entry: NodeIndex,
+    boundaries: &BTreeMap<NodeIndex, FunctionBoundary>,
+) -> FunctionBoundary {
+    let mut function = FunctionBoundary::default();
+    let mut worklist = vec![entry];
+
+    while let Some(idx) = worklist.pop() {
+        if !function.body.insert(idx) {
+            continue;
         }
-        Instruction::Jal(i) if i.rd() != 0 => {
-            compute_return_edge_position(graph, NodeIndex::new(idx.index() + 1))
+
+        if is_return(graph[idx].instruction) {
+            function.return_sites.insert(idx);
+        } else if is_call(graph[idx].instruction) {
+            let callees = graph
+                .edges(idx)
+                .filter(|e| *e.weight() == EdgeType::Call)
+                .map(|e| e.target())
+                .collect::<Vec<_>>();
+            let callee_returns = callees.is_empty()
+                || callees.iter().any(|callee| {
+                    boundaries
+                        .get(callee)
+                        .map_or(false, |f| !f.return_sites.is_empty())
+                });
+
+            if callee_returns {
+                worklist.extend(next_node(graph, idx));
+            }
+        } else {
+            worklist.extend(
+                graph
+                    .edges(idx)
+                    .filter(|e| !e.weight().is_stateful())
+                    .map(|e| e.target()),
+            );
         }
-        _ => graph
-            .edges(idx)
-            .flat_map(|e| compute_return_edge_position(graph, e.target()))
-            .collect(),
     }
+
+    function
 }
 
-/// Fix stateful edges and return a vector containing them
-fn construct_edge_if_stateful(idx: NodeIndex, graph: &ControlFlowGraph) -> Option<Vec<Edge>> {
-    match graph[idx] {
-        Instruction::Jal(jtype) if jtype.rd() != 0 => {
-            // jump and link => function call
-            let jump_dest = calculate_jal_destination(idx, jtype.imm());
-            let return_dest = NodeIndex::new(idx.index() + 1);
+/// Compute body and return sites of all functions starting at `entries`.
+///
+/// Whether a function returns depends on its callees (and on itself for
+/// recursive functions). Therefore all functions are explored repeatedly
+/// until a fixed point is reached. Return sites only grow in every round,
+/// which guarantees termination.
+pub fn compute_function_boundaries(
+    graph: &ControlFlowGraph,
+    entries: &BTreeSet<NodeIndex>,
+) -> BTreeMap<NodeIndex, FunctionBoundary> {
+    let mut boundaries = entries
+        .iter()
+        .map(|entry| (*entry, FunctionBoundary::default()))
+        .collect::<BTreeMap<_, _>>();
 
-            let mut edges = compute_return_edge_position(graph, jump_dest)
-                .iter()
-                .map(|rp| (*rp, return_dest, Some(idx)))
-                .collect::<Vec<Edge>>();
+    loop {
+        let mut changed = false;
 
-            let call_edge = (idx, jump_dest, Some(idx));
+        for entry in entries {
+            let function = explore_function(graph, *entry, &boundaries);
 
-            edges.push(call_edge);
+            if boundaries[entry] != function {
+                boundaries.insert(*entry, function);
+                changed = true;
+            }
+        }
 
-            Some(edges)
+        if !changed {
+            return boundaries;
         }
-        _ => None,
     }
 }
 
-/// Calculate stateful edges and return a vector containing them
-fn compute_stateful_edges(graph: &ControlFlowGraph) -> Vec<Edge> {
+/// Compute the destinations of all function calls (`Call` edges)
+fn compute_call_destinations(graph: &ControlFlowGraph) -> BTreeSet<NodeIndex> {
+    graph
+        .edge_references()
+        .filter(|e| *e.weight() == EdgeType::Call)
+        .map(|e| e.target())
+        .collect()
+}
+
+/// Compute the call edges of all direct function calls (`jal` with link)
+fn compute_call_edges(graph: &ControlFlowGraph) -> Vec<Edge> {
     graph
         .node_indices()
-        .filter_map(|idx| construct_edge_if_stateful(idx, graph))
-        .flatten()
+        .filter_map(|idx| match graph[idx].instruction {
+            Instruction::Jal(jtype) if jtype.rd() != 0 => Some((
+                idx,
+                node_at(
+                    graph,
+                    calculate_jal_destination(graph[idx].address, jtype.imm()),
+                )?,
+                EdgeType::Call,
+            )),
+            _ => None,
+        })
         .collect()
 }
 
-/// Get exit edge if possible
-fn find_possible_exit_edge(graph: &ControlFlowGraph, idx: NodeIndex) -> Option<EdgeIndex> {
-    let prev_idx = NodeIndex::new(idx.index() - 1);
-    let next_idx = NodeIndex::new(idx.index() + 1);
-    match graph[prev_idx] {
-        Instruction::Addi(a) => {
-            let edge = graph.find_edge(idx, next_idx);
-            if a.imm() == 93 {
-                edge
+/// Compute the jump and call edges of all resolved indirect jumps (`jalr`)
+fn compute_indirect_edges(
+    graph: &ControlFlowGraph,
+    targets: &BTreeMap<NodeIndex, BTreeSet<u64>>,
+) -> Vec<Edge> {
+    targets
+        .iter()
+        .flat_map(|(idx, destinations)| {
+            let edge_type = if is_call(graph[*idx].instruction) {
+                EdgeType::Call
             } else {
-                None
-            }
-        }
-        _ => None,
-    }
+                EdgeType::Jump
+            };
+
+            destinations
+                .iter()
+                .filter_map(move |dest| Some((*idx, node_at(graph, *dest)?, edge_type)))
+        })
+        .collect()
 }
 
-/// Fix the exit ecall edge
-fn fix_exit_ecall(graph: &mut ControlFlowGraph) {
-    graph.node_indices().for_each(|idx| {
-        if let Instruction::Ecall = graph[idx] {
-            if let Some(edge) = find_possible_exit_edge(graph, idx) {
+/// Compute the return edges from every callee back to the instruction after
+/// its call sites
+fn compute_return_edges(graph: &ControlFlowGraph) -> Vec<Edge> {
+    let boundaries = compute_function_boundaries(graph, &compute_call_destinations(graph));
+
+    graph
+        .edge_references()
+        .filter(|e| *e.weight() == EdgeType::Call)
+        .filter_map(|e| Some((e.source(), next_node(graph, e.source())?, e.target())))
+        .flat_map(|(call_site, return_dest, callee)| {
+            boundaries[&callee]
+                .return_sites
+                .iter()
+                .map(move |rp| (*rp, return_dest, EdgeType::Return(call_site)))
+        })
+        .collect()
+}
+
+/// Get all indirect jumps and calls (`jalr` except returns) without any
+/// resolved target.
+pub fn find_unresolved_indirect_jumps(graph: &ControlFlowGraph) -> Vec<NodeIndex> {
+    graph
+        .node_indices()
+        .filter(|idx| {
+            matches!(graph[*idx].instruction, Instruction::Jalr(_))
+                && !is_return(graph[*idx].instruction)
+        })
+        .filter(|idx| {
+            !graph
+                .edges(*idx)
+                .any(|e| matches!(e.weight(), EdgeType::Jump | EdgeType::Call))
+        })
+        .collect()
+}
+
+/// Remove the edges after all `ecall`s in `exits`, which never return
+fn fix_exit_ecalls(graph: &mut ControlFlowGraph, exits: &BTreeSet<NodeIndex>) {
+    exits.iter().for_each(|idx| {
+        if let Some(next) = next_node(graph, *idx) {
+            if let Some(edge) = graph.find_edge(*idx, next) {
                 graph.remove_edge(edge);
             }
         }
     })
 }
 
-/// Create a ControlFlowGraph from `u8` slice.
-fn build(binary: &[u8]) -> ControlFlowGraph {
-    let mut graph = create_instruction_graph(binary);
+/// Create a ControlFlowGraph from decoded `instructions`, which are executed
+/// from `entry_address`, and return it together with the targets of all
+/// indirect jumps.
+fn build_graph(
+    instructions: &BTreeMap<u64, Instruction>,
+    entry_address: u64,
+) -> (ControlFlowGraph, BTreeSet<u64>) {
+    let mut graph = create_instruction_graph(instructions);
 
     fn add_edges(graph: &mut ControlFlowGraph, edges: Vec<Edge>) {
         edges.iter().for_each(|e| {
@@ -192,56 +555,474 @@ fn build(binary: &[u8]) -> ControlFlowGraph {
     let pure_edges = compute_edges(&graph, construct_edge_if_pure);
     add_edges(&mut graph, pure_edges);
 
-    let jump_edges = compute_stateful_edges(&graph);
-    add_edges(&mut graph, jump_edges);
+    let call_edges = compute_call_edges(&graph);
+    add_edges(&mut graph, call_edges);
+
+    // Resolved indirect jumps make more code reachable, which might resolve
+    // even more indirect jumps. Exit syscalls make less code reachable
+    // (functions calling exit never return). Targets only grow, but exits are
+    // recomputed in every round, because a cut edge might have been the only
+    // reason for a syscall number to be known. Exits seen before with the
+    // same targets end the iteration, which guarantees termination.
+    let mut targets = BTreeMap::<NodeIndex, BTreeSet<u64>>::new();
+    let mut exits = BTreeSet::<NodeIndex>::new();
+    let mut seen = BTreeSet::<BTreeSet<NodeIndex>>::new();
+
+    loop {
+        let mut resolved = graph.clone();
+
+        fix_exit_ecalls(&mut resolved, &exits);
+
+        let indirect_edges = compute_indirect_edges(&resolved, &targets);
+        add_edges(&mut resolved, indirect_edges);
+
+        let return_edges = compute_return_edges(&resolved);
+        add_edges(&mut resolved, return_edges);
+
+        let states = node_at(&resolved, entry_address)
+            .map(|entry| propagate_values(&resolved, entry))
+            .unwrap_or_default();
+
+        let mut changed = false;
+        let mut found_exits = BTreeSet::<NodeIndex>::new();
+
+        for (idx, found) in resolve_indirect_jumps(&resolved, &states) {
+            let known = targets.entry(idx).or_default();
+            let before = known.len();
+
+            known.extend(found);
+            changed |= known.len() != before;
+        }
+
+        resolved
+            .node_indices()
+            .filter(|idx| resolved[*idx].instruction == Instruction::Ecall)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .for_each(|idx| {
+                // unreachable syscalls are unknown
+                resolved[idx].syscall = Some(
+                    states
+                        .get(&idx)
+                        .map_or(ValueSet::Top, |state| state.register(REG_A7).clone()),
+                );
+
+                if resolved[idx].is_syscall(SyscallId::Exit) {
+                    found_exits.insert(idx);
+                }
+            });
+
+        if changed {
+            seen.clear();
+        }
+
+        seen.insert(exits);
+
+        changed |= !seen.contains(&found_exits);
+        exits = found_exits;
+
+        if !changed {
+            return (resolved, targets.values().flatten().copied().collect());
+        }
+    }
+}
+
+/// Create a ControlFlowGraph from `u8` slice, which is loaded at virtual
+/// address `code_start`, with a linear sweep over all words. The program is
+/// executed from `entry_address`. Fails if a word cannot be decoded.
+pub fn build_from_entry(
+    binary: &[u8],
+    code_start: u64,
+    entry_address: u64,
+) -> Result<ControlFlowGraph, String> {
+    Ok(build_graph(&linear_sweep(binary, code_start)?, entry_address).0)
+}
+
+/// Create a ControlFlowGraph from `u8` slice, which is loaded at virtual
+/// address `code_start`, with a recursive traversal starting at
+/// `entry_address` and all other `entries`.
+/// Targets of indirect jumps are explored as soon as they are resolved.
+pub fn build_recursive(
+    binary: &[u8],
+    code_start: u64,
+    entry_address: u64,
+    entries: &BTreeSet<u64>,
+) -> ControlFlowGraph {
+    let mut seeds = entries.clone();
+
+    seeds.insert(entry_address);
+
+    loop {
+        let instructions = recursive_traversal(binary, code_start, &seeds);
+        let (graph, targets) = build_graph(&instructions, entry_address);
 
-    fix_exit_ecall(&mut graph);
+        let undiscovered = targets
+            .into_iter()
+            .filter(|address| !instructions.contains_key(address) && !seeds.contains(address))
+            .collect::<Vec<_>>();
+
+        if undiscovered.is_empty() {
+            return graph;
+        }
+
+        seeds.extend(undiscovered);
+    }
+}
+
+/// Compute all ranges `[start, end)` of the code segment, which are not
+/// covered by an instruction of `graph`.
+pub fn find_unreached_ranges(
+    graph: &ControlFlowGraph,
+    code_start: u64,
+    code_length: u64,
+) -> Vec<(u64, u64)> {
+    let code_end = code_start + code_length;
+
+    let mut ranges = vec![];
+    let mut start = code_start;
 
     graph
+        .raw_nodes()
+        .iter()
+        .map(|node| node.weight.address)
+        .chain(std::iter::once(code_end))
+        .for_each(|address| {
+            if start < address {
+                ranges.push((start, address.min(code_end)));
+            }
+            start = start.max(address + INSTRUCTION_SIZE);
+        });
+
+    ranges
 }
 
 pub type DataSegment = Vec<u8>;
 
-/// Create a ControlFlowGraph from Path `file`.
+/// Create a ControlFlowGraph from Path `file` with the given `strategy`.
+/// A recursive traversal starts at the entry point and at all function symbols.
+/// Graphs are reused from the persistent cache (see `cache`) if possible.
 // TODO: only tested with Selfie RISC-U file and relies on that ELF format
-pub fn build_from_file(file: &Path) -> Result<(ControlFlowGraph, DataSegment, ElfMetadata), &str> {
-    match load_file(file, 1024) {
-        Some((code, data, meta_data)) => Ok((build(code.as_slice()), data, meta_data)),
-        None => Err("Cannot load RISC-U ELF file"),
+pub fn build_from_file(
+    file: &Path,
+    strategy: Strategy,
+) -> Result<(ControlFlowGraph, DataSegment, ElfMetadata), String> {
+    let image = fs::read(file).map_err(|_| "Cannot load RISC-U ELF file")?;
+
+    let (code, data, meta_data) =
+        unsafe { load(image.as_slice(), 1024) }.ok_or("Cannot load RISC-U ELF file")?;
+
+    if let Some((graph, meta_data)) = cache::load(image.as_slice(), code.as_slice(), strategy) {
+        return Ok((graph, data, meta_data));
     }
-}
 
-/// Write ControlFlowGraph `graph` to dot file at `file` Path.
-pub fn write_to_file(graph: &ControlFlowGraph, file: &Path) -> Result<(), std::io::Error> {
-    let dot_graph = Dot::with_config(graph, &[]);
+    let graph = match strategy {
+        Strategy::LinearSweep => build_from_entry(
+            code.as_slice(),
+            meta_data.code_start,
+            meta_data.entry_address,
+        )?,
+        Strategy::RecursiveTraversal => {
+            let entries = meta_data
+                .functions
+                .iter()
+                .map(|symbol| symbol.address)
+                .collect();
 
-    let mut file = File::create(file)?;
+            build_recursive(
+                code.as_slice(),
+                meta_data.code_start,
+                meta_data.entry_address,
+                &entries,
+            )
+        }
+    };
 
-    file.write_fmt(format_args!("{:?}", dot_graph))?;
+    cache::store(image.as_slice(), &graph, &meta_data, strategy);
 
-    Ok(())
+    Ok((graph, data, meta_data))
 }
 
-/// Convert a dot file into a png file (depends on graphviz)
-pub fn convert_dot_to_png(source: &Path, output: &Path) -> Result<(), &'static str> {
-    Command::new("dot")
-        .arg("-Tpng")
-        .arg(source.to_path_buf())
-        .arg("-o")
-        .arg(output.to_path_buf())
-        .output()
-        .map_err(|_| "Cannot convert CFG to png file (is graphviz installed?)")?;
+/// Label of a node or edge in dot files, which is written as is
+pub struct DotLabel(pub String);
 
-    Ok(())
+impl fmt::Debug for DotLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Encode instruction words as a little endian code segment
+/// Create a ControlFlowGraph from `u8` slice, which is loaded at virtual
+/// address `code_start` and executed from its first instruction.
+#[cfg(test)]
+pub fn build(binary: &[u8], code_start: u64) -> ControlFlowGraph {
+    build_from_entry(binary, code_start, code_start).unwrap()
+}
+
+#[cfg(test)]
+pub fn assemble(words: &[u32]) -> Vec<u8> {
+    words
+        .iter()
+        .flat_map(|w| w.to_le_bytes().to_vec())
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use petgraph::dot::Dot;
     use serial_test::serial;
     use std::env::current_dir;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::process::Command;
     use std::string::String;
 
+    fn edge_types(graph: &ControlFlowGraph, idx: usize) -> Vec<EdgeType> {
+        let mut types = graph
+            .edges(NodeIndex::new(idx))
+            .map(|e| *e.weight())
+            .collect::<Vec<_>>();
+        types.sort_by_key(|t| format!("{:?}", t));
+        types
+    }
+
+    #[test]
+    fn can_type_control_flow_edges() {
+        let binary = assemble(&[
+            0x00a28663, // beq t0, a0, 12
+            0x014000ef, // jal ra, 20
+            0x0080006f, // jal zero, 8
+            0x00000013, // nop
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+
+        let graph = build(binary.as_slice(), 0);
+
+        assert_eq!(
+            edge_types(&graph, 0),
+            vec![EdgeType::BranchNotTaken, EdgeType::BranchTaken]
+        );
+        assert_eq!(edge_types(&graph, 1), vec![EdgeType::Call]);
+        assert_eq!(edge_types(&graph, 2), vec![EdgeType::Jump]);
+        assert_eq!(edge_types(&graph, 3), vec![EdgeType::Fallthrough]);
+        assert!(
+            edge_types(&graph, 5).is_empty(),
+            "exit ecall has no successor"
+        );
+        assert_eq!(
+            edge_types(&graph, 6),
+            vec![EdgeType::Return(NodeIndex::new(1))]
+        );
+    }
+
+    fn return_edges(graph: &ControlFlowGraph) -> Vec<(usize, usize, usize)> {
+        let mut edges = graph
+            .edge_references()
+            .filter_map(|e| match e.weight() {
+                EdgeType::Return(call_site) => {
+                    Some((e.source().index(), e.target().index(), call_site.index()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn can_report_undecodable_words_of_linear_sweep() {
+        let binary = assemble(&[
+            0x00000013, // nop
+            0xffffffff, // no instruction
+        ]);
+
+        let error = build_from_entry(binary.as_slice(), 0x10000, 0x10000).unwrap_err();
+
+        assert!(
+            error.contains("0x10004"),
+            "error names the address: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn can_compute_return_sites_of_functions_with_loops() {
+        let binary = assemble(&[
+            0x00c000ef, // jal ra, 12
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00050863, // beq a0, zero, 16
+            0xfff50513, // addi a0, a0, -1
+            0xfe550ce3, // beq a0, t0, -8
+            0x00008067, // jalr zero, 0(ra)
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+
+        let graph = build(binary.as_slice(), 0);
+
+        assert_eq!(return_edges(&graph), vec![(6, 1, 0), (7, 1, 0)]);
+    }
+
+    #[test]
+    fn can_compute_return_sites_of_recursive_functions() {
+        let binary = assemble(&[
+            0x00c000ef, // jal ra, 12
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00050663, // beq a0, zero, 12
+            0xffdff0ef, // jal ra, -4
+            0x00008067, // jalr zero, 0(ra)
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+
+        let graph = build(binary.as_slice(), 0);
+
+        let entries = vec![NodeIndex::new(3)].into_iter().collect();
+        let boundaries = compute_function_boundaries(&graph, &entries);
+        let function = &boundaries[&NodeIndex::new(3)];
+
+        assert_eq!(function.body.len(), 4);
+        assert_eq!(function.return_sites.len(), 2);
+        assert_eq!(
+            return_edges(&graph),
+            vec![(5, 1, 0), (5, 5, 4), (6, 1, 0), (6, 5, 4)]
+        );
+    }
+
+    #[test]
+    fn functions_calling_exit_do_not_return() {
+        let binary = assemble(&[
+            0x008000ef, // jal ra, 8
+            0x00000013, // nop
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+
+        let graph = build(binary.as_slice(), 0);
+
+        assert!(return_edges(&graph).is_empty());
+    }
+
+    #[test]
+    fn can_identify_syscall_numbers() {
+        let binary = assemble(&[
+            0x00000073, // ecall
+            0x05d00893, // addi a7, zero, 93
+            0x00000513, // addi a0, zero, 0
+            0x00000073, // ecall
+        ]);
+
+        let graph = build(binary.as_slice(), 0);
+
+        assert_eq!(graph[NodeIndex::new(0)].syscall, Some(ValueSet::Top));
+        assert_eq!(
+            graph[NodeIndex::new(3)].syscall,
+            Some(ValueSet::constant(93))
+        );
+        assert!(graph[NodeIndex::new(3)].is_syscall(SyscallId::Exit));
+        assert!(
+            !graph[NodeIndex::new(0)].may_be_syscall(SyscallId::Exit),
+            "unknown syscalls are not exits"
+        );
+        assert_eq!(graph[NodeIndex::new(1)].syscall, None);
+        assert_eq!(edge_types(&graph, 0), vec![EdgeType::SyscallExit]);
+        assert!(edge_types(&graph, 3).is_empty(), "exit has no successor");
+    }
+
+    #[test]
+    fn can_skip_data_with_recursive_traversal() {
+        let binary = assemble(&[
+            0x0080006f, // jal zero, 8
+            0xffffffff, // data
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00000000, // padding
+        ]);
+
+        let entries = vec![0x1000].into_iter().collect();
+        let graph = build_recursive(binary.as_slice(), 0x1000, 0x1000, &entries);
+
+        assert_eq!(
+            graph
+                .raw_nodes()
+                .iter()
+                .map(|node| node.weight.address)
+                .collect::<Vec<_>>(),
+            vec![0x1000, 0x1008, 0x100c]
+        );
+        assert_eq!(edge_types(&graph, 0), vec![EdgeType::Jump]);
+        assert_eq!(node_at(&graph, 0x1008), Some(NodeIndex::new(1)));
+        assert_eq!(node_at(&graph, 0x1004), None);
+        assert_eq!(
+            find_unreached_ranges(&graph, 0x1000, binary.len() as u64),
+            vec![(0x1004, 0x1008), (0x1010, 0x1014)]
+        );
+    }
+
+    #[test]
+    fn can_resolve_indirect_calls() {
+        let binary = assemble(&[
+            0x00000317, // auipc t1, 0
+            0x01430313, // addi t1, t1, 20
+            0x000300e7, // jalr ra, 0(t1)
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00100513, // addi a0, zero, 1
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+
+        let graph = build(binary.as_slice(), 0x10000);
+
+        assert_eq!(edge_types(&graph, 2), vec![EdgeType::Call]);
+        assert!(graph.contains_edge(NodeIndex::new(2), NodeIndex::new(5)));
+        assert_eq!(
+            edge_types(&graph, 6),
+            vec![EdgeType::Return(NodeIndex::new(2))]
+        );
+        assert!(find_unresolved_indirect_jumps(&graph).is_empty());
+    }
+
+    #[test]
+    fn can_resolve_tail_calls() {
+        let binary = assemble(&[
+            0x00c000ef, // jal ra, 12
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00000317, // auipc t1, 0
+            0x00c30313, // addi t1, t1, 12
+            0x00030067, // jalr zero, 0(t1)
+            0x00008067, // jalr zero, 0(ra)
+        ]);
+
+        let graph = build(binary.as_slice(), 0);
+
+        assert_eq!(edge_types(&graph, 5), vec![EdgeType::Jump]);
+        assert!(
+            graph.contains_edge(NodeIndex::new(6), NodeIndex::new(1)),
+            "tail called function returns to the original caller"
+        );
+    }
+
+    #[test]
+    fn marks_unresolved_indirect_jumps() {
+        let binary = assemble(&[
+            0x00053303, // ld t1, 0(a0)
+            0x00030067, // jalr zero, 0(t1)
+        ]);
+
+        let graph = build(binary.as_slice(), 0);
+
+        assert_eq!(
+            find_unresolved_indirect_jumps(&graph),
+            vec![NodeIndex::new(1)]
+        );
+        assert!(edge_types(&graph, 1).is_empty());
+    }
+
     // TODO: write a unit test without dependency on selfie and external files
     #[test]
     #[serial]
@@ -263,7 +1044,7 @@ mod tests {
 
         let test_file = Path::new("symbolic/division-by-zero-3-35.riscu.o");
 
-        let (graph, _, _) = build_from_file(test_file).unwrap();
+        let (graph, _, _) = build_from_file(test_file, Strategy::LinearSweep).unwrap();
 
         let dot_graph = Dot::with_config(&graph, &[]);
 
@@ -1,4 +1,5 @@
-use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg};
+use crate::cfg::Strategy;
+use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg, ArgMatches};
 
 pub fn args() -> App<'static> {
     App::new(crate_name!())
@@ -70,8 +71,194 @@ pub fn args() -> App<'static> {
                         .short('f')
                         .long("format")
                         .takes_value(true)
-                        .possible_values(&["dot", "png"])
+                        .possible_values(&[
+                            "dot", "png", "svg", "pdf", "json", "graphml", "mermaid",
+                        ])
                         .default_value("dot"),
+                )
+                .arg(
+                    Arg::with_name("granularity")
+                        .about("Granularity of the nodes of the generated CFG")
+                        .short('g')
+                        .long("granularity")
+                        .takes_value(true)
+                        .possible_values(&["instruction", "block", "call-graph"])
+                        .default_value("instruction"),
+                )
+                .arg(strategy_arg())
+                .arg(
+                    Arg::with_name("function")
+                        .about("Only generate the CFG of this function (name or entry address)")
+                        .long("function")
+                        .takes_value(true)
+                        .value_name("NAME|ADDRESS"),
+                )
+                .arg(
+                    Arg::with_name("annotate")
+                        .about("Annotate dominators, post-dominators and natural loops")
+                        .short('a')
+                        .long("annotate"),
+                )
+                .arg(
+                    Arg::with_name("stats")
+                        .about("Print statistics instead of generating the CFG (JSON with --format json)")
+                        .long("stats"),
                 ),
         )
+        .subcommand(
+            App::new("reach")
+                .about(
+                    "Check if a program point is statically reachable from the entry of a RISC-U ELF binary \
+                     (no concrete input is computed, as there is no solver yet)",
+                )
+                .arg(
+                    Arg::with_name("input-file")
+                        .about("Source RISC-U binary to be analyzed")
+                        .short('c')
+                        .long("input-file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .about("Program point to be reached")
+                        .short('t')
+                        .long("target")
+                        .takes_value(true)
+                        .value_name("ADDRESS|SYMBOL[+OFFSET]")
+                        .required(true),
+                )
+                .arg(strategy_arg()),
+        )
+        .subcommand(
+            App::new("paths")
+                .about("Select candidate paths from the entry to bug targets of a RISC-U ELF binary")
+                .arg(
+                    Arg::with_name("input-file")
+                        .about("Source RISC-U binary to be analyzed")
+                        .short('c')
+                        .long("input-file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(strategy_arg())
+                .arg(
+                    Arg::with_name("search")
+                        .about("Strategy to select the next candidate path")
+                        .long("search")
+                        .takes_value(true)
+                        .possible_values(&["shortest-first", "depth-first", "random", "uncovered-branch-first"])
+                        .default_value("shortest-first"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .about("Seed of the random search")
+                        .long("seed")
+                        .takes_value(true)
+                        .value_name("NUMBER")
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .about("Maximum number of candidate paths")
+                        .short('n')
+                        .long("limit")
+                        .takes_value(true)
+                        .value_name("NUMBER")
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("loop-bound")
+                        .about("Maximum number of loop iterations on a path")
+                        .long("loop-bound")
+                        .takes_value(true)
+                        .value_name("NUMBER")
+                        .default_value("2"),
+                )
+                .arg(
+                    Arg::with_name("call-depth")
+                        .about("Maximum depth of calls on a path")
+                        .long("call-depth")
+                        .takes_value(true)
+                        .value_name("NUMBER")
+                        .default_value("8"),
+                ),
+        )
+        .subcommand(
+            App::new("smt")
+                .about("Write the SMT-LIB formula of the first encodable candidate path to an exit or division of a RISC-U ELF binary")
+                .arg(
+                    Arg::with_name("input-file")
+                        .about("Source RISC-U binary to be analyzed")
+                        .short('c')
+                        .long("input-file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output-file")
+                        .about("Output file to write to")
+                        .short('o')
+                        .long("output-file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .default_value("formula.smt2"),
+                )
+                .arg(strategy_arg())
+                .arg(
+                    Arg::with_name("loop-bound")
+                        .about("Maximum number of loop iterations on a path")
+                        .long("loop-bound")
+                        .takes_value(true)
+                        .value_name("NUMBER")
+                        .default_value("2"),
+                )
+                .arg(
+                    Arg::with_name("call-depth")
+                        .about("Maximum depth of calls on a path")
+                        .long("call-depth")
+                        .takes_value(true)
+                        .value_name("NUMBER")
+                        .default_value("8"),
+                ),
+        )
+}
+
+/// Argument selecting the strategy to discover the instructions of the code
+/// segment (shared by all subcommands building a CFG)
+fn strategy_arg() -> Arg<'static> {
+    Arg::with_name("strategy")
+        .about("Strategy to discover the instructions of the code segment")
+        .short('s')
+        .long("strategy")
+        .takes_value(true)
+        .possible_values(&["linear-sweep", "recursive-traversal"])
+        .default_value("linear-sweep")
+}
+
+/// Parse the argument of `strategy_arg`.
+pub fn strategy(args: &ArgMatches) -> Strategy {
+    match args.value_of("strategy") {
+        Some("recursive-traversal") => Strategy::RecursiveTraversal,
+        _ => Strategy::LinearSweep,
+    }
+}
+
+/// Parse a non-negative number of argument `name`.
+pub fn parse_number(name: &str, value: &str) -> Result<u64, String> {
+    value
+        .parse::<u64>()
+        .map_err(|_| format!("{} is not a valid value for {}", value, name))
+}
+
+/// Parse a virtual address in hexadecimal (`0x` prefix) or decimal notation.
+pub fn parse_address(value: &str) -> Result<u64, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    }
+    .map_err(|_| format!("{} is not a valid address", value))
 }
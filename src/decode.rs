@@ -32,24 +32,35 @@ impl<R: RiscU> Decoder<'_, R> {
 impl<R: RiscU> Decoder<'_, R> {
     pub fn run(&mut self, instruction: u32) {
         match decode(instruction) {
-            Ok(instr) => match instr {
-                Instruction::Lui(i) => self.next.lui(i),
-                Instruction::Addi(i) => self.next.addi(i),
-                Instruction::Add(i) => self.next.add(i),
-                Instruction::Sub(i) => self.next.sub(i),
-                Instruction::Mul(i) => self.next.mul(i),
-                Instruction::Divu(i) => self.next.divu(i),
-                Instruction::Remu(i) => self.next.remu(i),
-                Instruction::Sltu(i) => self.next.sltu(i),
-                Instruction::Ld(i) => self.next.ld(i),
-                Instruction::Sd(i) => self.next.sd(i),
-                Instruction::Jal(i) => self.next.jal(i),
-                Instruction::Jalr(i) => self.next.jalr(i),
-                Instruction::Beq(i) => self.next.beq(i),
-                Instruction::Ecall => self.next.ecall(),
-                _ => unimplemented!(),
-            },
+            Ok(instr) => {
+                if self.execute(instr).is_none() {
+                    unimplemented!()
+                }
+            }
             _ => unimplemented!(),
         }
     }
+
+    /// Dispatch a decoded instruction (`None` if it is not part of RISC-U)
+    pub fn execute(&mut self, instruction: Instruction) -> Option<()> {
+        match instruction {
+            Instruction::Lui(i) => self.next.lui(i),
+            Instruction::Addi(i) => self.next.addi(i),
+            Instruction::Add(i) => self.next.add(i),
+            Instruction::Sub(i) => self.next.sub(i),
+            Instruction::Mul(i) => self.next.mul(i),
+            Instruction::Divu(i) => self.next.divu(i),
+            Instruction::Remu(i) => self.next.remu(i),
+            Instruction::Sltu(i) => self.next.sltu(i),
+            Instruction::Ld(i) => self.next.ld(i),
+            Instruction::Sd(i) => self.next.sd(i),
+            Instruction::Jal(i) => self.next.jal(i),
+            Instruction::Jalr(i) => self.next.jalr(i),
+            Instruction::Beq(i) => self.next.beq(i),
+            Instruction::Ecall => self.next.ecall(),
+            _ => return None,
+        }
+
+        Some(())
+    }
 }
@@ -3,6 +3,7 @@
 use crate::elf::load_file;
 use byteorder::{ByteOrder, LittleEndian};
 use riscv_decode::types::*;
+use riscv_decode::Instruction;
 use std::path::Path;
 
 use crate::decode::{Decoder, RiscU};
@@ -23,128 +24,141 @@ fn reg_to_str(reg: u32) -> String {
     }
 }
 
-struct Disassembler {}
+/// Disassembler, which prints every line right away or collects it in
+/// `lines` (if present)
+struct Disassembler {
+    lines: Option<Vec<String>>,
+}
+
+impl Disassembler {
+    fn emit(&mut self, line: String) {
+        match &mut self.lines {
+            Some(lines) => lines.push(line),
+            None => println!("{}", line),
+        }
+    }
+}
 
 impl RiscU for Disassembler {
     fn lui(&mut self, i: UType) {
-        println!("lui {},{:#x}", reg_to_str(i.rd()), i.imm())
+        self.emit(format!("lui {},{:#x}", reg_to_str(i.rd()), i.imm()))
     }
 
     // TODO: fix representation of negativ immediate values
     fn addi(&mut self, i: IType) {
         if i.rd() == 0 && i.rs1() == 0 && i.imm() == 0 {
-            println!("nop")
+            self.emit(String::from("nop"))
         } else {
-            println!(
+            self.emit(format!(
                 "addi {},{},{}",
                 reg_to_str(i.rd()),
                 reg_to_str(i.rs1()),
                 i.imm()
-            )
+            ))
         }
     }
 
     fn add(&mut self, i: RType) {
-        println!(
+        self.emit(format!(
             "add {},{},{}",
             reg_to_str(i.rd()),
             reg_to_str(i.rs1()),
             reg_to_str(i.rs2())
-        )
+        ))
     }
 
     fn sub(&mut self, i: RType) {
-        println!(
+        self.emit(format!(
             "sub {},{},{}",
             reg_to_str(i.rd()),
             reg_to_str(i.rs1()),
             reg_to_str(i.rs2())
-        )
+        ))
     }
 
     fn mul(&mut self, i: RType) {
-        println!(
+        self.emit(format!(
             "mul {},{},{}",
             reg_to_str(i.rd()),
             reg_to_str(i.rs1()),
             reg_to_str(i.rs2())
-        )
+        ))
     }
 
     fn divu(&mut self, i: RType) {
-        println!(
+        self.emit(format!(
             "divu {},{},{}",
             reg_to_str(i.rd()),
             reg_to_str(i.rs1()),
             reg_to_str(i.rs2())
-        )
+        ))
     }
 
     fn remu(&mut self, i: RType) {
-        println!(
+        self.emit(format!(
             "remu {},{},{}",
             reg_to_str(i.rd()),
             reg_to_str(i.rs1()),
             reg_to_str(i.rs2())
-        )
+        ))
     }
 
     fn sltu(&mut self, i: RType) {
-        println!(
+        self.emit(format!(
             "sltu {},{},{}",
             reg_to_str(i.rd()),
             reg_to_str(i.rs1()),
             reg_to_str(i.rs2())
-        )
+        ))
     }
 
     fn ld(&mut self, i: IType) {
-        println!(
+        self.emit(format!(
             "ld {},{}({})",
             reg_to_str(i.rd()),
             i.imm(),
             reg_to_str(i.rs1())
-        )
+        ))
     }
 
     fn sd(&mut self, i: SType) {
-        println!(
+        self.emit(format!(
             "sd {},{}({})",
             reg_to_str(i.rs2()),
             i.imm(),
             reg_to_str(i.rs1())
-        )
+        ))
     }
 
     fn jal(&mut self, i: JType) {
-        println!("jal {},{}", reg_to_str(i.rd()), i.imm())
+        self.emit(format!("jal {},{}", reg_to_str(i.rd()), i.imm()))
     }
 
     fn jalr(&mut self, i: IType) {
-        println!(
+        self.emit(format!(
             "jalr {},{}({})",
             reg_to_str(i.rd()),
             i.imm(),
             reg_to_str(i.rs1())
-        )
+        ))
     }
 
     fn beq(&mut self, i: BType) {
-        println!(
+        self.emit(format!(
             "beq {},{},{}",
             reg_to_str(i.rs1()),
             reg_to_str(i.rs2()),
             i.imm()
-        )
+        ))
     }
 
     fn ecall(&mut self) {
-        println!("ecall")
+        self.emit(String::from("ecall"))
     }
 }
 
 pub fn disassemble(binary: &[u8]) {
-    let mut disassembler = Disassembler {};
+    let mut disassembler = Disassembler { lines: None };
     let mut pipeline = Decoder::new(&mut disassembler);
 
     binary
@@ -153,6 +167,19 @@ pub fn disassemble(binary: &[u8]) {
         .for_each(|x| pipeline.run(x));
 }
 
+/// Disassemble a single instruction (instructions, which are not part of
+/// RISC-U, are printed in their debug representation).
+pub fn disassemble_instruction(instruction: Instruction) -> String {
+    let mut disassembler = Disassembler {
+        lines: Some(vec![]),
+    };
+
+    match Decoder::new(&mut disassembler).execute(instruction) {
+        Some(()) => disassembler.lines.unwrap_or_default().concat(),
+        None => format!("{:?}", instruction),
+    }
+}
+
 // TODO: only tested with Selfie RISC-U file and relies on that ELF format
 pub fn disassemble_riscu(file: &Path) -> Result<(), &str> {
     match load_file(file, 1024) {
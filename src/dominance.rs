@@ -0,0 +1,541 @@
+//! # Structural analyses of control flow graphs
+//!
+//! This module computes dominator and post-dominator trees and detects
+//! natural loops. All analyses work on any petgraph `Graph` (instruction
+//! graphs, basic block graphs and function CFGs). They should be applied to
+//! single functions, because call and return edges of the program CFG do not
+//! form loops in the sense of this module.
+//!
+//! - `a` dominates `b`, if every path from the entry to `b` contains `a`
+//! - `a` post-dominates `b`, if every path from `b` to an exit contains `a`
+//! - an edge `u -> h` is a back edge, if `h` dominates `u`
+//! - the natural loop of a back edge `u -> h` consists of `h` and all nodes
+//!   reaching `u` without passing through `h` (loops with the same header are
+//!   merged)
+
+use crate::call_graph::{CallGraph, FunctionCfg};
+use crate::cfg::{ControlFlowGraph, DotLabel, EdgeType};
+use petgraph::algo::dominators::simple_fast;
+use petgraph::dot::Dot;
+use petgraph::graph::{EdgeReference, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Graph;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Dominator (or post-dominator) tree of a graph
+#[derive(Clone, Debug)]
+pub struct DominatorTree {
+    /// Root of the tree (`None` for the virtual exit of post-dominator trees)
+    root: Option<NodeIndex>,
+    immediate: HashMap<NodeIndex, NodeIndex>,
+}
+
+#[allow(dead_code)]
+impl DominatorTree {
+    /// Get the immediate (post-)dominator of `node`.
+    ///
+    /// Returns `None` for roots and for nodes not part of the tree.
+    pub fn immediate_dominator(&self, node: NodeIndex) -> Option<NodeIndex> {
+        self.immediate.get(&node).copied()
+    }
+
+    /// Check if `node` is part of the tree (reachable from the entry or
+    /// reaching an exit respectively).
+    pub fn contains(&self, node: NodeIndex) -> bool {
+        self.root == Some(node) || self.immediate.contains_key(&node)
+    }
+
+    /// Get all (post-)dominators of `node` starting with `node` itself.
+    pub fn dominators(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut dominators = vec![node];
+
+        while let Some(dominator) = self.immediate_dominator(dominators[dominators.len() - 1]) {
+            dominators.push(dominator);
+        }
+
+        dominators
+    }
+
+    /// Check if `a` (post-)dominates `b`.
+    pub fn dominates(&self, a: NodeIndex, b: NodeIndex) -> bool {
+        self.dominators(b).contains(&a)
+    }
+
+    /// Get all nodes immediately (post-)dominated by `node`.
+    pub fn children(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut children = self
+            .immediate
+            .iter()
+            .filter(|(_, dominator)| **dominator == node)
+            .map(|(child, _)| *child)
+            .collect::<Vec<_>>();
+
+        children.sort();
+        children
+    }
+}
+
+/// Compute the dominator tree of `graph` rooted at `entry`.
+pub fn compute_dominators<N, E>(graph: &Graph<N, E>, entry: NodeIndex) -> DominatorTree {
+    let dominators = simple_fast(graph, entry);
+
+    DominatorTree {
+        root: Some(entry),
+        immediate: graph
+            .node_indices()
+            .filter_map(|n| dominators.immediate_dominator(n).map(|d| (n, d)))
+            .collect(),
+    }
+}
+
+/// Compute the post-dominator tree of `graph`.
+///
+/// All nodes without successors are connected to a virtual exit, which is
+/// the root of the tree. Nodes post-dominated by the virtual exit only have
+/// no immediate post-dominator.
+pub fn compute_post_dominators<N, E>(graph: &Graph<N, E>) -> DominatorTree {
+    let mut reversed = Graph::<(), ()>::with_capacity(graph.node_count() + 1, graph.edge_count());
+
+    graph.node_indices().for_each(|_| {
+        reversed.add_node(());
+    });
+
+    let exit = reversed.add_node(());
+
+    graph.edge_references().for_each(|e| {
+        reversed.add_edge(e.target(), e.source(), ());
+    });
+
+    graph
+        .node_indices()
+        .filter(|n| graph.neighbors(*n).next().is_none())
+        .for_each(|n| {
+            reversed.add_edge(exit, n, ());
+        });
+
+    let dominators = simple_fast(&reversed, exit);
+
+    DominatorTree {
+        root: None,
+        immediate: graph
+            .node_indices()
+            .filter_map(|n| {
+                dominators
+                    .immediate_dominator(n)
+                    .filter(|d| *d != exit)
+                    .map(|d| (n, d))
+            })
+            .collect(),
+    }
+}
+
+/// A natural loop of a control flow graph
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NaturalLoop {
+    pub header: NodeIndex,
+    /// All back edges (latch, header) to the header
+    pub back_edges: Vec<(NodeIndex, NodeIndex)>,
+    pub body: BTreeSet<NodeIndex>,
+    /// All nodes outside of the loop, which are targets of edges leaving the loop
+    pub exits: BTreeSet<NodeIndex>,
+    /// Nesting depth (1 for outermost loops)
+    pub depth: usize,
+    /// Innermost loop containing this loop (index into the loops vector)
+    pub parent: Option<usize>,
+}
+
+/// Compute the body of the natural loop with back edge `latch -> header`
+fn compute_loop_body<N, E>(
+    graph: &Graph<N, E>,
+    header: NodeIndex,
+    latch: NodeIndex,
+) -> BTreeSet<NodeIndex> {
+    let mut body = BTreeSet::new();
+    let mut worklist = vec![latch];
+
+    body.insert(header);
+
+    while let Some(node) = worklist.pop() {
+        if body.insert(node) {
+            worklist.extend(graph.neighbors_directed(node, petgraph::Incoming));
+        }
+    }
+
+    body
+}
+
+/// Find all natural loops of `graph` ordered by their header.
+pub fn find_natural_loops<N, E>(
+    graph: &Graph<N, E>,
+    dominators: &DominatorTree,
+) -> Vec<NaturalLoop> {
+    let mut back_edges = BTreeMap::<NodeIndex, Vec<(NodeIndex, NodeIndex)>>::new();
+
+    graph
+        .edge_references()
+        .filter(|e| dominators.contains(e.source()))
+        .filter(|e| dominators.dominates(e.target(), e.source()))
+        .for_each(|e| {
+            back_edges
+                .entry(e.target())
+                .or_insert_with(Vec::new)
+                .push((e.source(), e.target()));
+        });
+
+    let mut loops = back_edges
+        .into_iter()
+        .map(|(header, back_edges)| {
+            let body = back_edges
+                .iter()
+                .flat_map(|(latch, _)| compute_loop_body(graph, header, *latch))
+                .collect::<BTreeSet<_>>();
+
+            let exits = body
+                .iter()
+                .flat_map(|n| graph.neighbors(*n))
+                .filter(|n| !body.contains(n))
+                .collect();
+
+            NaturalLoop {
+                header,
+                back_edges,
+                body,
+                exits,
+                depth: 1,
+                parent: None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Back edges are merged per header, so every header owns exactly one loop
+    // and a loop only encloses another one if its body is a strict superset.
+    let nesting = (0..loops.len())
+        .map(|i| {
+            let enclosing = (0..loops.len())
+                .filter(|j| {
+                    loops[*j].body.len() > loops[i].body.len()
+                        && loops[*j].body.is_superset(&loops[i].body)
+                })
+                .collect::<Vec<_>>();
+
+            let parent = enclosing
+                .iter()
+                .min_by_key(|j| loops[**j].body.len())
+                .copied();
+
+            (enclosing.len() + 1, parent)
+        })
+        .collect::<Vec<_>>();
+
+    loops
+        .iter_mut()
+        .zip(nesting)
+        .for_each(|(l, (depth, parent))| {
+            l.depth = depth;
+            l.parent = parent;
+        });
+
+    loops
+}
+
+/// Dominators, post-dominators and natural loops of a single graph
+pub struct StructuralAnalysis {
+    pub dominators: DominatorTree,
+    pub post_dominators: DominatorTree,
+    pub loops: Vec<NaturalLoop>,
+}
+
+#[allow(dead_code)]
+impl StructuralAnalysis {
+    /// Run all structural analyses on `graph` starting at `entry`.
+    pub fn new<N, E>(graph: &Graph<N, E>, entry: NodeIndex) -> Self {
+        let dominators = compute_dominators(graph, entry);
+        let post_dominators = compute_post_dominators(graph);
+        let loops = find_natural_loops(graph, &dominators);
+
+        Self {
+            dominators,
+            post_dominators,
+            loops,
+        }
+    }
+
+    /// Get the number of loops containing `node`.
+    pub fn loop_depth(&self, node: NodeIndex) -> usize {
+        self.loops.iter().filter(|l| l.body.contains(&node)).count()
+    }
+
+    /// Get the loop `node` is the header of, if any.
+    pub fn loop_with_header(&self, node: NodeIndex) -> Option<&NaturalLoop> {
+        self.loops.iter().find(|l| l.header == node)
+    }
+
+    /// Check if `source -> target` is a back edge of a natural loop.
+    pub fn is_back_edge(&self, source: NodeIndex, target: NodeIndex) -> bool {
+        self.loops
+            .iter()
+            .any(|l| l.back_edges.contains(&(source, target)))
+    }
+}
+
+/// Structural annotation of a single instruction
+#[derive(Default)]
+struct Annotation {
+    /// Address of the immediate dominator
+    immediate_dominator: Option<u64>,
+    /// Address of the immediate post-dominator
+    immediate_post_dominator: Option<u64>,
+    loop_depth: usize,
+    loop_header: bool,
+}
+
+/// Analyze every function and map the results back to the program `cfg`
+/// (instructions shared by multiple functions are annotated once).
+fn annotate_program(
+    cfg: &ControlFlowGraph,
+    call_graph: &CallGraph,
+) -> (
+    HashMap<NodeIndex, Annotation>,
+    HashSet<(NodeIndex, NodeIndex)>,
+) {
+    let mut annotations = HashMap::new();
+    let mut back_edges = HashSet::new();
+
+    call_graph.graph.node_indices().for_each(|function| {
+        let function_cfg = call_graph.function_cfg(cfg, function);
+        let (local_annotations, local_back_edges) = annotate_function(&function_cfg);
+
+        local_annotations.into_iter().for_each(|(idx, annotation)| {
+            annotations.entry(idx).or_insert(annotation);
+        });
+        back_edges.extend(local_back_edges);
+    });
+
+    (annotations, back_edges)
+}
+
+/// Analyze a single function, all results are indexed by program `NodeIndex`.
+fn annotate_function(
+    function: &FunctionCfg,
+) -> (
+    HashMap<NodeIndex, Annotation>,
+    HashSet<(NodeIndex, NodeIndex)>,
+) {
+    let analysis = StructuralAnalysis::new(&function.graph, function.entry);
+    let global = |idx: NodeIndex| function.instructions[idx.index()];
+    let address = |idx: NodeIndex| function.graph[idx].address;
+
+    let annotations = function
+        .graph
+        .node_indices()
+        .map(|idx| {
+            let annotation = Annotation {
+                immediate_dominator: analysis.dominators.immediate_dominator(idx).map(address),
+                immediate_post_dominator: analysis
+                    .post_dominators
+                    .immediate_dominator(idx)
+                    .map(address),
+                loop_depth: analysis.loop_depth(idx),
+                loop_header: analysis.loop_with_header(idx).is_some(),
+            };
+
+            (global(idx), annotation)
+        })
+        .collect();
+
+    let back_edges = analysis
+        .loops
+        .iter()
+        .flat_map(|l| l.back_edges.iter())
+        .map(|(latch, header)| (global(*latch), global(*header)))
+        .collect();
+
+    (annotations, back_edges)
+}
+
+fn format_annotation(annotation: Option<&Annotation>) -> String {
+    let address = |address: Option<u64>| match address {
+        Some(address) => format!("{:#x}", address),
+        None => String::from("-"),
+    };
+
+    match annotation {
+        Some(a) => format!(
+            "idom: {} ipdom: {}{}{}",
+            address(a.immediate_dominator),
+            address(a.immediate_post_dominator),
+            if a.loop_depth > 0 {
+                format!("\nloop depth: {}", a.loop_depth)
+            } else {
+                String::new()
+            },
+            if a.loop_header { " (header)" } else { "" },
+        ),
+        None => String::from("unreachable"),
+    }
+}
+
+/// Write the annotated graph, whose nodes are instructions of the program.
+fn write_annotated(
+    graph: &ControlFlowGraph,
+    program_index: &dyn Fn(NodeIndex) -> NodeIndex,
+    annotations: &HashMap<NodeIndex, Annotation>,
+    back_edges: &HashSet<(NodeIndex, NodeIndex)>,
+    file: &Path,
+) -> Result<(), std::io::Error> {
+    let labeled = graph.map(
+        |idx, node| {
+            DotLabel(format!(
+                "{:?}\n{}",
+                node,
+                format_annotation(annotations.get(&program_index(idx)))
+            ))
+        },
+        |_, edge_type| *edge_type,
+    );
+
+    let edge_attributes = |_, e: EdgeReference<EdgeType>| {
+        if back_edges.contains(&(program_index(e.source()), program_index(e.target()))) {
+            String::from("color = red")
+        } else {
+            String::new()
+        }
+    };
+
+    let dot_graph = Dot::with_attr_getters(&labeled, &[], &edge_attributes, &|_, _| String::new());
+
+    let mut file = File::create(file)?;
+
+    file.write_fmt(format_args!("{:?}", dot_graph))?;
+
+    Ok(())
+}
+
+/// Write the program `cfg` annotated with dominators, post-dominators and
+/// natural loops (back edges are red) to a dot file at `file` Path.
+pub fn write_annotated_to_file(
+    cfg: &ControlFlowGraph,
+    call_graph: &CallGraph,
+    file: &Path,
+) -> Result<(), std::io::Error> {
+    let (annotations, back_edges) = annotate_program(cfg, call_graph);
+
+    write_annotated(cfg, &|idx| idx, &annotations, &back_edges, file)
+}
+
+/// Write the CFG of a single function annotated with dominators,
+/// post-dominators and natural loops to a dot file at `file` Path.
+pub fn write_annotated_function_to_file(
+    function: &FunctionCfg,
+    file: &Path,
+) -> Result<(), std::io::Error> {
+    let (annotations, back_edges) = annotate_function(function);
+
+    write_annotated(
+        &function.graph,
+        &|idx| function.instructions[idx.index()],
+        &annotations,
+        &back_edges,
+        file,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(indices: &[usize]) -> BTreeSet<NodeIndex> {
+        indices.iter().map(|i| NodeIndex::new(*i)).collect()
+    }
+
+    // 0 -> 1 -> 2 -> 3 -> 4 -> 1, 1 -> 5, 3 -> 3
+    fn nested_loops() -> Graph<(), ()> {
+        Graph::from_edges(&[(0, 1), (1, 2), (1, 5), (2, 3), (3, 3), (3, 4), (4, 1)])
+    }
+
+    #[test]
+    fn can_compute_dominators() {
+        let graph = nested_loops();
+        let dominators = compute_dominators(&graph, NodeIndex::new(0));
+
+        assert_eq!(dominators.immediate_dominator(NodeIndex::new(0)), None);
+        assert_eq!(
+            dominators.immediate_dominator(NodeIndex::new(5)),
+            Some(NodeIndex::new(1))
+        );
+        assert!(dominators.dominates(NodeIndex::new(2), NodeIndex::new(4)));
+        assert!(!dominators.dominates(NodeIndex::new(4), NodeIndex::new(5)));
+        assert_eq!(
+            dominators.children(NodeIndex::new(1)),
+            vec![NodeIndex::new(2), NodeIndex::new(5)]
+        );
+    }
+
+    #[test]
+    fn can_compute_post_dominators() {
+        let diamond = Graph::<(), ()>::from_edges(&[(0, 1), (0, 2), (1, 3), (2, 3)]);
+        let post_dominators = compute_post_dominators(&diamond);
+
+        assert_eq!(
+            post_dominators.immediate_dominator(NodeIndex::new(0)),
+            Some(NodeIndex::new(3))
+        );
+        assert_eq!(post_dominators.immediate_dominator(NodeIndex::new(3)), None);
+
+        let post_dominators = compute_post_dominators(&nested_loops());
+
+        assert_eq!(
+            post_dominators.immediate_dominator(NodeIndex::new(3)),
+            Some(NodeIndex::new(4))
+        );
+        assert_eq!(
+            post_dominators.immediate_dominator(NodeIndex::new(1)),
+            Some(NodeIndex::new(5))
+        );
+    }
+
+    #[test]
+    fn can_find_nested_natural_loops() {
+        let graph = nested_loops();
+        let analysis = StructuralAnalysis::new(&graph, NodeIndex::new(0));
+
+        assert_eq!(analysis.loops.len(), 2);
+
+        let outer = &analysis.loops[0];
+        let inner = &analysis.loops[1];
+
+        assert_eq!(outer.header, NodeIndex::new(1));
+        assert_eq!(outer.body, nodes(&[1, 2, 3, 4]));
+        assert_eq!(outer.exits, nodes(&[5]));
+        assert_eq!(outer.depth, 1);
+        assert_eq!(outer.parent, None);
+
+        assert_eq!(inner.header, NodeIndex::new(3));
+        assert_eq!(inner.body, nodes(&[3]));
+        assert_eq!(inner.exits, nodes(&[4]));
+        assert_eq!(inner.depth, 2);
+        assert_eq!(inner.parent, Some(0));
+
+        assert_eq!(analysis.loop_depth(NodeIndex::new(3)), 2);
+        assert!(analysis.is_back_edge(NodeIndex::new(4), NodeIndex::new(1)));
+        assert!(!analysis.is_back_edge(NodeIndex::new(1), NodeIndex::new(2)));
+    }
+
+    #[test]
+    fn can_merge_back_edges_of_the_same_header() {
+        let graph = Graph::<(), ()>::from_edges(&[(0, 1), (1, 2), (1, 3), (2, 1), (3, 1), (1, 4)]);
+        let analysis = StructuralAnalysis::new(&graph, NodeIndex::new(0));
+
+        assert_eq!(analysis.loops.len(), 1);
+
+        let merged = analysis.loop_with_header(NodeIndex::new(1)).unwrap();
+
+        assert_eq!(merged.back_edges.len(), 2);
+        assert_eq!(merged.body, nodes(&[1, 2, 3]));
+        assert_eq!(merged.depth, 1);
+        assert_eq!(merged.parent, None);
+    }
+}
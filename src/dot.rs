@@ -0,0 +1,234 @@
+//! # Handle dot rendering of control flow graphs
+//!
+//! Every instruction is labeled with its address and disassembly. Edges are
+//! colored by their `EdgeType`, the instructions of every function are grouped
+//! into a cluster and bug targets (see `candidate_path::find_roots`) are
+//! highlighted. Basic blocks and call graphs are rendered the same way (see
+//! `write_graph`). Dot files can be converted to png, svg and pdf with graphviz.
+
+use crate::call_graph::CallGraph;
+use crate::cfg::{ControlFlowGraph, EdgeType};
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::Command;
+
+/// Dot attributes of an edge of kind `edge_type`
+pub fn edge_style(edge_type: EdgeType) -> &'static str {
+    match edge_type {
+        EdgeType::Fallthrough => "",
+        EdgeType::BranchTaken => "color = darkgreen",
+        EdgeType::BranchNotTaken => "color = darkorange",
+        EdgeType::Jump => "color = blue",
+        EdgeType::Call => "color = purple, style = dashed",
+        EdgeType::Return(_) => "color = purple, style = dotted",
+        EdgeType::SyscallExit => "color = gray",
+    }
+}
+
+/// Escape `label` for a quoted dot string (lines are left aligned).
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\l")
+}
+
+/// Cluster (identifier and label) of the instruction `idx`, which is the
+/// first function of `call_graph` containing it
+pub fn function_cluster(call_graph: &CallGraph, idx: NodeIndex) -> Option<(usize, String)> {
+    call_graph
+        .functions_containing(idx)
+        .first()
+        .map(|function| (function.index(), call_graph.graph[*function].name.clone()))
+}
+
+/// Write `graph` to a dot file at `file` Path. Every node is labeled by
+/// `label` and put into the cluster given by `cluster` (if any), every edge
+/// is labeled and styled by `edge` and all `highlighted` nodes are filled.
+pub fn write_graph<N, E>(
+    graph: &Graph<N, E>,
+    label: impl Fn(NodeIndex) -> String,
+    edge: impl Fn(&E) -> (String, &'static str),
+    cluster: impl Fn(NodeIndex) -> Option<(usize, String)>,
+    highlighted: &BTreeSet<NodeIndex>,
+    file: &Path,
+) -> Result<(), std::io::Error> {
+    let mut clusters = BTreeMap::<usize, (String, Vec<NodeIndex>)>::new();
+    let mut unclustered = vec![];
+
+    graph.node_indices().for_each(|idx| match cluster(idx) {
+        Some((id, name)) => clusters
+            .entry(id)
+            .or_insert_with(|| (name, vec![]))
+            .1
+            .push(idx),
+        None => unclustered.push(idx),
+    });
+
+    let node = |idx: NodeIndex| {
+        format!(
+            "{} [ label = \"{}\"{} ]",
+            idx.index(),
+            escape(&label(idx)),
+            if highlighted.contains(&idx) {
+                ", style = filled, fillcolor = salmon"
+            } else {
+                ""
+            }
+        )
+    };
+
+    let mut file = File::create(file)?;
+
+    writeln!(file, "digraph {{")?;
+    writeln!(file, "    node [ shape = box, fontname = \"monospace\" ]")?;
+
+    for (id, (name, nodes)) in clusters.iter() {
+        writeln!(file, "    subgraph cluster_{} {{", id)?;
+        writeln!(file, "        label = \"{}\"", escape(name))?;
+
+        for idx in nodes {
+            writeln!(file, "        {}", node(*idx))?;
+        }
+
+        writeln!(file, "    }}")?;
+    }
+
+    for idx in unclustered {
+        writeln!(file, "    {}", node(idx))?;
+    }
+
+    for e in graph.edge_references() {
+        let (label, style) = edge(e.weight());
+
+        writeln!(
+            file,
+            "    {} -> {} [ label = \"{}\"{}{} ]",
+            e.source().index(),
+            e.target().index(),
+            escape(&label),
+            if style.is_empty() { "" } else { ", " },
+            style
+        )?;
+    }
+
+    writeln!(file, "}}")?;
+
+    Ok(())
+}
+
+/// Label and dot attributes of a control flow edge of kind `edge_type`
+pub fn edge_label(edge_type: &EdgeType) -> (String, &'static str) {
+    (format!("{:?}", edge_type), edge_style(*edge_type))
+}
+
+/// Write ControlFlowGraph `graph` to a dot file at `file` Path. Instructions
+/// are clustered by the functions of `call_graph` (instructions shared by
+/// multiple functions belong to the first one) and all `highlighted`
+/// instructions are filled.
+pub fn write_to_file(
+    graph: &ControlFlowGraph,
+    call_graph: Option<&CallGraph>,
+    highlighted: &BTreeSet<NodeIndex>,
+    file: &Path,
+) -> Result<(), std::io::Error> {
+    write_graph(
+        graph,
+        |idx| format!("{:?}\n", graph[idx]),
+        edge_label,
+        |idx| call_graph.and_then(|cg| function_cluster(cg, idx)),
+        highlighted,
+        file,
+    )
+}
+
+/// Convert a dot file into a png, svg or pdf file (depends on graphviz)
+pub fn convert_dot(source: &Path, output: &Path, format: &str) -> Result<(), String> {
+    let result = Command::new("dot")
+        .arg(format!("-T{}", format))
+        .arg(source)
+        .arg("-o")
+        .arg(output)
+        .output()
+        .map_err(|_| {
+            format!(
+                "Cannot convert CFG to {} file (is graphviz installed?)",
+                format
+            )
+        })?;
+
+    if result.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&result.stderr).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_block::{self, BasicBlocks};
+    use crate::cfg::{self, assemble};
+    use crate::elf::ElfMetadata;
+    use std::fs;
+
+    #[test]
+    fn can_render_clusters_and_bug_targets() {
+        let graph = cfg::build(
+            assemble(&[
+                0x00c000ef, // jal ra, 12
+                0x05d00893, // addi a7, zero, 93
+                0x00000073, // ecall
+                0x02b55533, // divu a0, a0, a1
+                0x00008067, // jalr zero, 0(ra)
+            ])
+            .as_slice(),
+            0x10000,
+        );
+        let call_graph = CallGraph::build(
+            &graph,
+            &ElfMetadata {
+                entry_address: 0x10000,
+                code_length: 20,
+                code_start: 0x10000,
+                functions: vec![],
+            },
+        );
+        let highlighted = vec![NodeIndex::new(3)].into_iter().collect();
+
+        let file = std::env::temp_dir().join("monster-dot-test.dot");
+
+        write_to_file(&graph, Some(&call_graph), &highlighted, &file).unwrap();
+
+        let dot = fs::read_to_string(&file).unwrap();
+        let _ = fs::remove_file(&file);
+
+        assert!(dot.contains("label = \"0x1000c: divu a0,a0,a1"));
+        assert!(dot.contains("3 [ label = \"0x1000c: divu a0,a0,a1\\l\", style = filled"));
+        assert!(dot.contains("label = \"0x1000c\""), "cluster of callee");
+        assert!(dot.contains("0 -> 3 [ label = \"Call\", color = purple, style = dashed ]"));
+
+        let blocks = BasicBlocks::build(&graph);
+
+        basic_block::write_to_file(
+            &blocks,
+            &graph,
+            |idx| function_cluster(&call_graph, idx),
+            &file,
+        )
+        .unwrap();
+
+        let dot = fs::read_to_string(&file).unwrap();
+        let _ = fs::remove_file(&file);
+
+        assert!(
+            dot.contains("subgraph cluster_1 {"),
+            "cluster of callee blocks"
+        );
+        assert!(dot.contains("[ label = \"Call\", color = purple, style = dashed ]"));
+    }
+}
@@ -3,6 +3,7 @@
 use byteorder::{ByteOrder, LittleEndian};
 use goblin::elf::{
     header::header64::Header, program_header::program_header64::ProgramHeader, program_header::*,
+    Elf,
 };
 use std::fs;
 use std::path::Path;
@@ -50,12 +51,45 @@ impl ReadRaw for [u8] {
     }
 }
 
+/// Function symbol of an ELF image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// The virtual address of the first instruction.
+    pub address: u64,
+    pub size: u64,
+}
+
 /// ELF image metadata.
 #[derive(Clone, Debug)]
 pub struct ElfMetadata {
     /// The entry virtual address.
     pub entry_address: u64,
     pub code_length: u64,
+    /// The virtual address of the code segment.
+    pub code_start: u64,
+    /// All function symbols (empty for stripped binaries).
+    pub functions: Vec<Symbol>,
+}
+
+/// Extract all function symbols from the symbol table (if there is one).
+fn extract_function_symbols(image: &[u8]) -> Vec<Symbol> {
+    match Elf::parse(image) {
+        Ok(elf) => elf
+            .syms
+            .iter()
+            .filter(|sym| sym.is_function() && sym.st_value != 0)
+            .filter_map(|sym| match elf.strtab.get(sym.st_name) {
+                Some(Ok(name)) if !name.is_empty() => Some(Symbol {
+                    name: String::from(name),
+                    address: sym.st_value,
+                    size: sym.st_size,
+                }),
+                _ => None,
+            })
+            .collect(),
+        Err(_) => vec![],
+    }
 }
 
 pub fn load_file(
@@ -81,6 +115,8 @@ pub unsafe fn load(image: &[u8], memory_limit: usize) -> Option<(Vec<u8>, Vec<u8
     // let va_space = 0.. memory_in_bytes - 1;
     let mut memory: Vec<u8> = vec![0; memory_in_bytes];
 
+    let mut code_start = header.e_entry;
+
     let mut segments = &image[header.e_phoff as usize..];
     for _ in 0..header.e_phnum {
         let ph: ProgramHeader = segments.read_raw()?;
@@ -115,6 +151,7 @@ pub unsafe fn load(image: &[u8], memory_limit: usize) -> Option<(Vec<u8>, Vec<u8
             &image[(ph.p_offset as usize)..((ph.p_offset as usize) + (ph.p_filesz as usize))];
 
         memory = Vec::from(slice);
+        code_start = ph.p_vaddr;
 
         println!("{:?}", ph);
         // memory[0..ph.p_filesz as usize].clone_from_slice(
@@ -142,6 +179,8 @@ pub unsafe fn load(image: &[u8], memory_limit: usize) -> Option<(Vec<u8>, Vec<u8
         ElfMetadata {
             entry_address: header.e_entry,
             code_length,
+            code_start,
+            functions: extract_function_symbols(image),
         },
     ))
 }
@@ -0,0 +1,250 @@
+//! # Handle machine-readable exports of control flow graphs
+//!
+//! Besides dot (see `dot`), a `ControlFlowGraph` can be exported as
+//! - JSON: nodes with address and disassembled instruction, typed edges
+//! - GraphML: for graph tools like Gephi, yEd or networkx
+//! - Mermaid: flowcharts for documentation
+//!
+//! Nodes are identified by their virtual address in all formats.
+
+use crate::cfg::{ControlFlowGraph, EdgeType};
+use crate::disassemble::disassemble_instruction;
+use petgraph::visit::EdgeRef;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Name of the kind of an edge (the call site of returns is exported separately)
+pub fn edge_kind(edge_type: EdgeType) -> &'static str {
+    match edge_type {
+        EdgeType::Fallthrough => "fallthrough",
+        EdgeType::BranchTaken => "branch-taken",
+        EdgeType::BranchNotTaken => "branch-not-taken",
+        EdgeType::Jump => "jump",
+        EdgeType::Call => "call",
+        EdgeType::Return(_) => "return",
+        EdgeType::SyscallExit => "syscall-exit",
+    }
+}
+
+/// Escape `value` for a JSON string.
+fn escape_json(value: &str) -> String {
+    value.chars().fold(String::new(), |mut escaped, c| {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+        escaped
+    })
+}
+
+/// Escape `value` for XML attributes and text.
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write ControlFlowGraph `graph` as JSON object with a `nodes` and an
+/// `edges` array to `file` Path.
+pub fn write_json(graph: &ControlFlowGraph, file: &Path) -> Result<(), std::io::Error> {
+    let nodes = graph
+        .node_indices()
+        .map(|idx| {
+            format!(
+                "    {{ \"address\": {}, \"instruction\": \"{}\" }}",
+                graph[idx].address,
+                escape_json(&disassemble_instruction(graph[idx].instruction))
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let edges = graph
+        .edge_references()
+        .map(|edge| {
+            let call_site = match *edge.weight() {
+                EdgeType::Return(call_site) => {
+                    format!(", \"call_site\": {}", graph[call_site].address)
+                }
+                _ => String::new(),
+            };
+
+            format!(
+                "    {{ \"source\": {}, \"target\": {}, \"type\": \"{}\"{} }}",
+                graph[edge.source()].address,
+                graph[edge.target()].address,
+                edge_kind(*edge.weight()),
+                call_site
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut file = File::create(file)?;
+
+    writeln!(file, "{{")?;
+    writeln!(file, "  \"nodes\": [\n{}\n  ],", nodes.join(",\n"))?;
+    writeln!(file, "  \"edges\": [\n{}\n  ]", edges.join(",\n"))?;
+    writeln!(file, "}}")?;
+
+    Ok(())
+}
+
+/// Write ControlFlowGraph `graph` as GraphML to `file` Path.
+pub fn write_graphml(graph: &ControlFlowGraph, file: &Path) -> Result<(), std::io::Error> {
+    let mut file = File::create(file)?;
+
+    writeln!(file, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        file,
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+    )?;
+    writeln!(
+        file,
+        "  <key id=\"address\" for=\"node\" attr.name=\"address\" attr.type=\"long\"/>"
+    )?;
+    writeln!(
+        file,
+        "  <key id=\"instruction\" for=\"node\" attr.name=\"instruction\" attr.type=\"string\"/>"
+    )?;
+    writeln!(
+        file,
+        "  <key id=\"type\" for=\"edge\" attr.name=\"type\" attr.type=\"string\"/>"
+    )?;
+    writeln!(
+        file,
+        "  <key id=\"call_site\" for=\"edge\" attr.name=\"call_site\" attr.type=\"long\"/>"
+    )?;
+    writeln!(file, "  <graph id=\"cfg\" edgedefault=\"directed\">")?;
+
+    for idx in graph.node_indices() {
+        writeln!(file, "    <node id=\"{:#x}\">", graph[idx].address)?;
+        writeln!(
+            file,
+            "      <data key=\"address\">{}</data>",
+            graph[idx].address
+        )?;
+        writeln!(
+            file,
+            "      <data key=\"instruction\">{}</data>",
+            escape_xml(&disassemble_instruction(graph[idx].instruction))
+        )?;
+        writeln!(file, "    </node>")?;
+    }
+
+    for edge in graph.edge_references() {
+        writeln!(
+            file,
+            "    <edge source=\"{:#x}\" target=\"{:#x}\">",
+            graph[edge.source()].address,
+            graph[edge.target()].address
+        )?;
+        writeln!(
+            file,
+            "      <data key=\"type\">{}</data>",
+            edge_kind(*edge.weight())
+        )?;
+        if let EdgeType::Return(call_site) = *edge.weight() {
+            writeln!(
+                file,
+                "      <data key=\"call_site\">{}</data>",
+                graph[call_site].address
+            )?;
+        }
+        writeln!(file, "    </edge>")?;
+    }
+
+    writeln!(file, "  </graph>")?;
+    writeln!(file, "</graphml>")?;
+
+    Ok(())
+}
+
+/// Write ControlFlowGraph `graph` as Mermaid flowchart to `file` Path.
+pub fn write_mermaid(graph: &ControlFlowGraph, file: &Path) -> Result<(), std::io::Error> {
+    let mut file = File::create(file)?;
+
+    writeln!(file, "flowchart TD")?;
+
+    for idx in graph.node_indices() {
+        let label = format!("{:?}", graph[idx]).replace('"', "#quot;");
+
+        writeln!(file, "    n{:x}[\"{}\"]", graph[idx].address, label)?;
+    }
+
+    for edge in graph.edge_references() {
+        let arrow = match *edge.weight() {
+            EdgeType::Call | EdgeType::Return(_) => "-.->",
+            _ => "-->",
+        };
+
+        writeln!(
+            file,
+            "    n{:x} {}|{}| n{:x}",
+            graph[edge.source()].address,
+            arrow,
+            edge_kind(*edge.weight()),
+            graph[edge.target()].address
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+    use std::fs;
+
+    fn export(
+        name: &str,
+        write: fn(&ControlFlowGraph, &Path) -> Result<(), std::io::Error>,
+    ) -> String {
+        let graph = cfg::build(
+            assemble(&[
+                0x008000ef, // jal ra, 8
+                0x00000013, // nop
+                0x00008067, // jalr zero, 0(ra)
+            ])
+            .as_slice(),
+            0x10000,
+        );
+
+        let file = std::env::temp_dir().join(format!("monster-export.{}", name));
+
+        write(&graph, &file).unwrap();
+
+        let exported = fs::read_to_string(&file).unwrap();
+        let _ = fs::remove_file(&file);
+
+        exported
+    }
+
+    #[test]
+    fn can_export_typed_edges_by_address() {
+        let json = export("json", write_json);
+
+        assert!(json.contains("{ \"address\": 65544, \"instruction\": \"jalr zero,0(ra)\" }"));
+        assert!(json.contains("{ \"source\": 65536, \"target\": 65544, \"type\": \"call\" }"));
+        assert!(json.contains(
+            "{ \"source\": 65544, \"target\": 65540, \"type\": \"return\", \"call_site\": 65536 }"
+        ));
+
+        let graphml = export("graphml", write_graphml);
+
+        assert!(graphml.contains("<node id=\"0x10004\">"));
+        assert!(graphml.contains("<edge source=\"0x10000\" target=\"0x10008\">"));
+        assert!(graphml.contains("<data key=\"call_site\">65536</data>"));
+
+        let mermaid = export("mmd", write_mermaid);
+
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        assert!(mermaid.contains("n10000[\"0x10000: jal ra,8\"]"));
+        assert!(mermaid.contains("n10008 -.->|return| n10004"));
+    }
+}
@@ -24,26 +24,6 @@ pub enum SyscallId {
     Brk = 214,
 }
 
-fn instruction_to_str(i: Instruction) -> &'static str {
-    match i {
-        Instruction::Lui(_) => "lui",
-        Instruction::Jal(_) => "jal",
-        Instruction::Jalr(_) => "jalr",
-        Instruction::Beq(_) => "beq",
-        Instruction::Ld(_) => "ld",
-        Instruction::Sd(_) => "sd",
-        Instruction::Addi(_) => "addi",
-        Instruction::Add(_) => "add",
-        Instruction::Sub(_) => "sub",
-        Instruction::Sltu(_) => "sltu",
-        Instruction::Mul(_) => "mul",
-        Instruction::Divu(_) => "divu",
-        Instruction::Remu(_) => "remu",
-        Instruction::Ecall => "ecall",
-        _ => "unknown",
-    }
-}
-
 #[derive(Clone, Debug, Copy, Eq, Hash, PartialEq)]
 pub enum ArgumentSide {
     Lhs,
@@ -64,7 +44,7 @@ pub struct Instr {
     // Divu(rtype) -> 2 IE / 1 OE
     // Remu(rtype) -> 2 IE / 1 OE
     // Sltu(rtype) -> 2 IE / 1 OE
-    instruction: Instruction,
+    pub instruction: Instruction,
 }
 
 impl Instr {
@@ -82,7 +62,7 @@ impl fmt::Debug for Instr {
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub struct Const {
     // can have multiple output edges, but no input edge
-    value: u64,
+    pub value: u64,
 }
 
 impl Const {
@@ -100,7 +80,7 @@ impl fmt::Debug for Const {
 #[derive(Clone, Eq, Hash, PartialEq)]
 pub struct Input {
     // can have multiple output edges, but no input edge
-    name: String,
+    pub name: String,
 }
 
 impl Input {
@@ -115,17 +95,71 @@ impl fmt::Debug for Input {
     }
 }
 
-#[derive(Clone, Eq, Hash, PartialEq)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum BooleanFunction {
-    // Equals,
+    // comparisons of bit vectors (2 IE)
+    Equals,
+    NotEquals,
     GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    SignedGreaterThan,
+    SignedGreaterThanOrEqual,
+    SignedLessThan,
+    SignedLessThanOrEqual,
+    // connectives of other Constrain nodes (2 IE, Not: 1 IE on Lhs)
+    And,
+    Or,
+    Not,
+}
+
+impl BooleanFunction {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BooleanFunction::Equals => "==",
+            BooleanFunction::NotEquals => "!=",
+            BooleanFunction::GreaterThan => ">",
+            BooleanFunction::GreaterThanOrEqual => ">=",
+            BooleanFunction::LessThan => "<",
+            BooleanFunction::LessThanOrEqual => "<=",
+            BooleanFunction::SignedGreaterThan => ">s",
+            BooleanFunction::SignedGreaterThanOrEqual => ">=s",
+            BooleanFunction::SignedLessThan => "<s",
+            BooleanFunction::SignedLessThanOrEqual => "<=s",
+            BooleanFunction::And => "&&",
+            BooleanFunction::Or => "||",
+            BooleanFunction::Not => "!",
+        }
+    }
+
+    // evaluates the function for concrete arguments (booleans are 0 or 1)
+    pub fn evaluate(&self, lhs: u64, rhs: u64) -> bool {
+        match self {
+            BooleanFunction::Equals => lhs == rhs,
+            BooleanFunction::NotEquals => lhs != rhs,
+            BooleanFunction::GreaterThan => lhs > rhs,
+            BooleanFunction::GreaterThanOrEqual => lhs >= rhs,
+            BooleanFunction::LessThan => lhs < rhs,
+            BooleanFunction::LessThanOrEqual => lhs <= rhs,
+            BooleanFunction::SignedGreaterThan => (lhs as i64) > (rhs as i64),
+            BooleanFunction::SignedGreaterThanOrEqual => (lhs as i64) >= (rhs as i64),
+            BooleanFunction::SignedLessThan => (lhs as i64) < (rhs as i64),
+            BooleanFunction::SignedLessThanOrEqual => (lhs as i64) <= (rhs as i64),
+            BooleanFunction::And => lhs != 0 && rhs != 0,
+            BooleanFunction::Or => lhs != 0 || rhs != 0,
+            BooleanFunction::Not => lhs == 0,
+        }
+    }
 }
 
 #[derive(Clone, Eq, Hash, PartialEq)]
 pub struct Constrain {
-    // has 1 input edge only and 0 output edges
-    name: String,
-    op: BooleanFunction,
+    // comparisons have 2 input edges (bit vectors), connectives have 1 or 2 input
+    // edges (Constrain nodes), output edges only lead to connectives
+    pub name: String,
+    pub op: BooleanFunction,
 }
 
 impl Constrain {
@@ -158,6 +192,17 @@ pub enum Value {
 struct DataFlowGraphBuilder<'a> {
     graph: Formula,
     path: &'a [Instruction],
+    // decisions of all branches on the path (true if the branch is taken)
+    branch_decisions: &'a [bool],
+    branches_executed: usize,
+    // index of the executed instruction on the path (the last one is the target)
+    position: usize,
+    // path condition (Constrain nodes of all symbolic branches)
+    roots: Vec<NodeIndex>,
+    // set, if a concrete branch contradicts its branch decision
+    infeasible: bool,
+    // set, if the path can not be encoded as formula
+    error: Option<String>,
     program_break: u64,
     regs: [Value; 32],
     memory: Vec<Value>,
@@ -168,6 +213,7 @@ impl<'a> DataFlowGraphBuilder<'a> {
     fn new(
         memory_size: usize,
         path: &'a [Instruction],
+        branch_decisions: &'a [bool],
         data_segment: &[u8],
         elf_metadata: ElfMetadata,
     ) -> Self {
@@ -176,12 +222,6 @@ impl<'a> DataFlowGraphBuilder<'a> {
 
         regs[REG_SP] = Value::Concrete(memory_size as u64 - 8);
 
-        println!(
-            "data_segment.len(): {}   entry_address:  {}",
-            data_segment.len(),
-            elf_metadata.entry_address
-        );
-
         let start = (elf_metadata.entry_address / 8) as usize;
         let end = start + data_segment.len() / 8;
 
@@ -195,6 +235,12 @@ impl<'a> DataFlowGraphBuilder<'a> {
             graph: Formula::new(),
             program_break: elf_metadata.entry_address + (data_segment.len() as u64),
             path,
+            branch_decisions,
+            branches_executed: 0,
+            position: 0,
+            roots: vec![],
+            infeasible: false,
+            error: None,
             regs,
             memory,
         }
@@ -222,13 +268,6 @@ impl<'a> DataFlowGraphBuilder<'a> {
 
         let result = Value::Concrete(immediate);
 
-        println!(
-            "{}  imm: {:?} -> rd: {:?}",
-            instruction_to_str(Instruction::Lui(utype)),
-            immediate as i64,
-            result,
-        );
-
         self.regs[utype.rd() as usize] = result;
 
         None
@@ -252,14 +291,6 @@ impl<'a> DataFlowGraphBuilder<'a> {
 
         let result = self.execute_binary_op(instruction, rs1_value, Value::Concrete(immediate), op);
 
-        println!(
-            "{}  rs1: {:?} imm: {:?} -> rd: {:?}",
-            instruction_to_str(instruction),
-            rs1_value,
-            immediate as i64,
-            result,
-        );
-
         self.regs[itype.rd() as usize] = result;
 
         None
@@ -283,14 +314,6 @@ impl<'a> DataFlowGraphBuilder<'a> {
 
         let result = self.execute_binary_op(instruction, rs1_value, rs2_value, op);
 
-        println!(
-            "{}  rs1: {:?} rs2: {:?} -> rd: {:?}",
-            instruction_to_str(instruction),
-            rs1_value,
-            rs2_value,
-            result,
-        );
-
         self.regs[rtype.rd() as usize] = result;
 
         None
@@ -328,20 +351,165 @@ impl<'a> DataFlowGraphBuilder<'a> {
                 let res = self.create_result_node(instruction);
                 self.symbolic_op(v1, v2, res)
             }
-            // TODO: generate exit node here
-            _ => panic!("access to unitialized memory"),
+            _ => {
+                self.fail(String::from("access to uninitialized memory"));
+
+                Value::Uninitialized
+            }
         }
     }
 
-    pub fn generate_graph(&mut self) -> Option<(Formula, NodeIndex)> {
-        if let Some(root_idx) = self
-            .path
-            .iter()
-            .for_each_until_some(|instr| self.execute(*instr))
-        {
-            Some((self.graph.clone(), root_idx))
-        } else {
-            None
+    // stops the execution of the path, because it can not be encoded
+    fn fail(&mut self, message: String) -> Option<NodeIndex> {
+        self.error.get_or_insert(message);
+
+        None
+    }
+
+    // All returned roots have to be asserted: the path condition in path order and
+    // the bug condition as last root.
+    // Returns None, if the path does not reach a bug condition or is infeasible,
+    // and an error, if the path can not be encoded.
+    pub fn generate_graph(&mut self) -> Result<Option<(Formula, Vec<NodeIndex>)>, String> {
+        let path = self.path;
+
+        let root = path.iter().enumerate().for_each_until_some(|(i, instr)| {
+            self.position = i;
+
+            let root = self.execute(*instr);
+
+            if self.infeasible || self.error.is_some() {
+                Some(None)
+            } else {
+                root.map(Some)
+            }
+        });
+
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        match root {
+            Some(Some(root_idx)) => {
+                if self.branches_executed != self.branch_decisions.len() {
+                    return Err(format!(
+                        "{} branch decisions for {} branches",
+                        self.branch_decisions.len(),
+                        self.branches_executed
+                    ));
+                }
+
+                self.roots.push(root_idx);
+
+                Ok(Some((self.graph.clone(), self.roots.clone())))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn is_target(&self) -> bool {
+        self.position + 1 == self.path.len()
+    }
+
+    // A division at the end of the path is the target of a division by zero,
+    // hence the root constrains the divisor to zero. All other divisions have
+    // the RISC-V semantics (no trap on division by zero).
+    fn execute_division<Op>(
+        &mut self,
+        instruction: Instruction,
+        rtype: RType,
+        op: Op,
+    ) -> Option<NodeIndex>
+    where
+        Op: FnOnce(u64, u64) -> u64,
+    {
+        if !self.is_target() {
+            return self.execute_rtype(instruction, rtype, op);
+        }
+
+        let divisor = self.regs[rtype.rs2() as usize];
+
+        if divisor == Value::Uninitialized {
+            return self.fail(String::from("division by uninitialized memory"));
+        }
+
+        if let Value::Concrete(v) = divisor {
+            if v != 0 {
+                self.infeasible = true;
+
+                return None;
+            }
+        }
+
+        let divisor_idx = self.value_to_node(divisor);
+        let const_node_idx = self.create_const_node(0);
+
+        let root = Node::Constrain(Constrain::new(
+            String::from("divisor"),
+            BooleanFunction::Equals,
+        ));
+        let root_idx = self.graph.add_node(root);
+
+        self.graph
+            .add_edge(divisor_idx, root_idx, ArgumentSide::Lhs);
+        self.graph
+            .add_edge(const_node_idx, root_idx, ArgumentSide::Rhs);
+
+        Some(root_idx)
+    }
+
+    fn execute_branch(&mut self, instruction: Instruction, btype: BType) -> Option<NodeIndex> {
+        // a branch without decision is the last instruction of the path
+        let decision = match self.branch_decisions.get(self.branches_executed) {
+            Some(decision) => *decision,
+            None if self.is_target() => return None,
+            None => return self.fail(format!("no decision for branch {}", self.branches_executed)),
+        };
+
+        self.branches_executed += 1;
+
+        let rs1_value = self.regs[btype.rs1() as usize];
+        let rs2_value = self.regs[btype.rs2() as usize];
+
+        let op = branch_condition(instruction, decision);
+
+        let holds = match (rs1_value, rs2_value) {
+            (Value::Concrete(v1), Value::Concrete(v2)) => Some(op.evaluate(v1, v2)),
+            // both operands are the same symbolic value
+            (Value::Symbolic(v1), Value::Symbolic(v2)) if v1 == v2 => Some(op.evaluate(0, 0)),
+            (Value::Uninitialized, _) | (_, Value::Uninitialized) => {
+                return self.fail(String::from("branch on uninitialized memory"));
+            }
+            _ => None,
+        };
+
+        match holds {
+            Some(true) => {}
+            Some(false) => {
+                self.infeasible = true;
+            }
+            None => {
+                let lhs = self.value_to_node(rs1_value);
+                let rhs = self.value_to_node(rs2_value);
+
+                let constrain = Node::Constrain(Constrain::new(String::from(op.symbol()), op));
+                let constrain_idx = self.graph.add_node(constrain);
+
+                self.graph.add_edge(lhs, constrain_idx, ArgumentSide::Lhs);
+                self.graph.add_edge(rhs, constrain_idx, ArgumentSide::Rhs);
+
+                self.roots.push(constrain_idx);
+            }
+        }
+
+        None
+    }
+
+    fn value_to_node(&mut self, value: Value) -> NodeIndex {
+        match value {
+            Value::Concrete(v) => self.create_const_node(v),
+            Value::Symbolic(node) => node,
+            Value::Uninitialized => unreachable!("uninitialized operands are rejected before"),
         }
     }
 
@@ -353,9 +521,8 @@ impl<'a> DataFlowGraphBuilder<'a> {
             } else {
                 self.program_break = new_program_break;
             }
-            println!("new program break: {}", new_program_break);
         } else {
-            unimplemented!("can not handle symbolic or uninitialized program break")
+            return self.fail(String::from("symbolic or uninitialized program break"));
         }
         None
     }
@@ -372,19 +539,27 @@ impl<'a> DataFlowGraphBuilder<'a> {
                 let to_add = 8 - (size % 8);
                 let words_read = (size + to_add) / 8;
 
+                let first = (buffer / 8) as usize;
+
+                if words_read as usize > self.memory.len().saturating_sub(first) {
+                    return self.fail(format!("read buffer {:#x} outside of memory", buffer));
+                }
+
                 for i in 0..words_read {
                     let name = format!("read({}, {}, {})", 0, buffer, size);
                     let node = Node::Input(Input::new(name));
                     let node_idx = self.graph.add_node(node);
-                    self.memory[((buffer / 8) + i) as usize] = Value::Symbolic(node_idx);
+                    self.memory[first + i as usize] = Value::Symbolic(node_idx);
                 }
             } else {
-                unimplemented!("can not handle symbolic or uinitialized size in read syscall")
+                return self.fail(String::from(
+                    "symbolic or uninitialized size in read syscall",
+                ));
             }
         } else {
-            unimplemented!(
-                "can not handle symbolic or uninitialized buffer address in read syscall"
-            )
+            return self.fail(String::from(
+                "symbolic or uninitialized buffer address in read syscall",
+            ));
         }
         None
     }
@@ -406,7 +581,7 @@ impl<'a> DataFlowGraphBuilder<'a> {
 
             Some(root_idx)
         } else {
-            unimplemented!("exit only implemented for symbolic exit codes")
+            self.fail(String::from("exit with non-symbolic exit code"))
         }
     }
 
@@ -421,39 +596,36 @@ impl<'a> DataFlowGraphBuilder<'a> {
             Value::Concrete(syscall_id) if syscall_id == (SyscallId::Exit as u64) => {
                 self.execute_exit()
             }
-            Value::Concrete(x) => unimplemented!("this syscall ({}) is not implemented yet", x),
-            Value::Uninitialized => unimplemented!("ecall with uninitialized syscall id"),
-            Value::Symbolic(_) => unimplemented!("ecall with symbolic syscall id not implemented"),
+            Value::Concrete(x) => self.fail(format!("unsupported syscall {}", x)),
+            Value::Uninitialized => self.fail(String::from("ecall with uninitialized syscall id")),
+            Value::Symbolic(_) => self.fail(String::from("ecall with symbolic syscall id")),
         }
     }
 
-    fn execute_load(&mut self, instruction: Instruction, itype: IType) -> Option<NodeIndex> {
+    fn execute_load(&mut self, itype: IType) -> Option<NodeIndex> {
         if itype.rd() != 0 {
             if let Value::Concrete(base_address) = self.regs[itype.rs1() as usize] {
                 let immediate = sign_extend_itype_stype(itype.imm());
 
                 let address = base_address.wrapping_add(immediate);
 
-                let value = self.memory[(address / 8) as usize];
-
-                println!(
-                    "{} rs1: {:?} imm: {} -> rd: {:?}",
-                    instruction_to_str(instruction),
-                    self.regs[itype.rs1() as usize],
-                    immediate as i64,
-                    value,
-                );
+                let value = match self.memory.get((address / 8) as usize) {
+                    Some(value) => *value,
+                    None => {
+                        return self.fail(format!("load from {:#x} outside of memory", address))
+                    }
+                };
 
                 self.regs[itype.rd() as usize] = value;
             } else {
-                unimplemented!("can not handle symbolic addresses in LD")
+                return self.fail(String::from("symbolic or uninitialized address in LD"));
             }
         }
 
         None
     }
 
-    fn execute_store(&mut self, instruction: Instruction, stype: SType) -> Option<NodeIndex> {
+    fn execute_store(&mut self, stype: SType) -> Option<NodeIndex> {
         if let Value::Concrete(base_address) = self.regs[stype.rs1() as usize] {
             let immediate = sign_extend_itype_stype(stype.imm());
 
@@ -461,17 +633,12 @@ impl<'a> DataFlowGraphBuilder<'a> {
 
             let value = self.regs[stype.rs2() as usize];
 
-            println!(
-                "{}  immediate: {:?} rs2: {:?} rs1: {:?} -> ",
-                instruction_to_str(instruction),
-                immediate as i64,
-                self.regs[stype.rs1() as usize],
-                value,
-            );
-
-            self.memory[(address / 8) as usize] = value;
+            match self.memory.get_mut((address / 8) as usize) {
+                Some(word) => *word = value,
+                None => return self.fail(format!("store to {:#x} outside of memory", address)),
+            }
         } else {
-            unimplemented!("can not handle symbolic addresses in SD")
+            return self.fail(String::from("symbolic or uninitialized address in SD"));
         }
 
         None
@@ -485,14 +652,17 @@ impl<'a> DataFlowGraphBuilder<'a> {
             Instruction::Add(rtype) => self.execute_rtype(instruction, rtype, u64::wrapping_add),
             Instruction::Sub(rtype) => self.execute_rtype(instruction, rtype, u64::wrapping_sub),
             Instruction::Mul(rtype) => self.execute_rtype(instruction, rtype, u64::wrapping_mul),
-            // TODO: Implement exit for DIVU
-            Instruction::Divu(rtype) => self.execute_rtype(instruction, rtype, u64::wrapping_div),
-            Instruction::Remu(rtype) => self.execute_rtype(instruction, rtype, u64::wrapping_rem),
+            Instruction::Divu(rtype) => self.execute_division(instruction, rtype, |l, r| {
+                l.checked_div(r).unwrap_or(u64::MAX)
+            }),
+            Instruction::Remu(rtype) => {
+                self.execute_division(instruction, rtype, |l, r| l.checked_rem(r).unwrap_or(l))
+            }
             Instruction::Sltu(rtype) => {
                 self.execute_rtype(instruction, rtype, |l, r| if l < r { 1 } else { 0 })
             }
-            Instruction::Ld(itype) => self.execute_load(instruction, itype),
-            Instruction::Sd(stype) => self.execute_store(instruction, stype),
+            Instruction::Ld(itype) => self.execute_load(itype),
+            Instruction::Sd(stype) => self.execute_store(stype),
             Instruction::Jal(jtype) => {
                 if jtype.rd() != 0 {
                     self.regs[jtype.rd() as usize] = Value::Concrete(0);
@@ -505,8 +675,13 @@ impl<'a> DataFlowGraphBuilder<'a> {
                 }
                 None
             }
-            Instruction::Beq(_btype) => None,
-            _ => unimplemented!("can not handle this instruction"),
+            Instruction::Beq(btype)
+            | Instruction::Bne(btype)
+            | Instruction::Blt(btype)
+            | Instruction::Bge(btype)
+            | Instruction::Bltu(btype)
+            | Instruction::Bgeu(btype) => self.execute_branch(instruction, btype),
+            _ => self.fail(format!("unsupported instruction {:?}", instruction)),
         }
     }
 }
@@ -521,22 +696,97 @@ pub fn sign_extend(n: u64, b: u32) -> u64 {
     }
 }
 
-#[allow(dead_code)]
-fn sign_extend_utype(imm: u32) -> u64 {
-    sign_extend(imm as u64, 20)
+// the immediate of a utype is already shifted to the upper 20 bits
+pub fn sign_extend_utype(imm: u32) -> u64 {
+    sign_extend(imm as u64, 32)
 }
 
-fn sign_extend_itype_stype(imm: u32) -> u64 {
+pub fn sign_extend_itype_stype(imm: u32) -> u64 {
     sign_extend(imm as u64, 12)
 }
 
+// condition of a branch, which has to hold for the branch decision (true if taken)
+fn branch_condition(instruction: Instruction, decision: bool) -> BooleanFunction {
+    let (taken, not_taken) = match instruction {
+        Instruction::Beq(_) => (BooleanFunction::Equals, BooleanFunction::NotEquals),
+        Instruction::Bne(_) => (BooleanFunction::NotEquals, BooleanFunction::Equals),
+        Instruction::Blt(_) => (
+            BooleanFunction::SignedLessThan,
+            BooleanFunction::SignedGreaterThanOrEqual,
+        ),
+        Instruction::Bge(_) => (
+            BooleanFunction::SignedGreaterThanOrEqual,
+            BooleanFunction::SignedLessThan,
+        ),
+        Instruction::Bltu(_) => (
+            BooleanFunction::LessThan,
+            BooleanFunction::GreaterThanOrEqual,
+        ),
+        Instruction::Bgeu(_) => (
+            BooleanFunction::GreaterThanOrEqual,
+            BooleanFunction::LessThan,
+        ),
+        _ => unreachable!("{:?} is not a branch", instruction),
+    };
+
+    if decision {
+        taken
+    } else {
+        not_taken
+    }
+}
+
+// Combines all roots of a formula into a single root with And nodes (e.g. for
+// dead code elimination). Roots are folded left, hence every And node has 2
+// input edges.
 #[allow(dead_code)]
-fn build_dataflow_graph(
+pub fn conjunction(formula: &mut Formula, roots: &[NodeIndex]) -> NodeIndex {
+    let (first, rest) = roots
+        .split_first()
+        .expect("a formula has at least one root");
+
+    rest.iter().fold(*first, |lhs, rhs| {
+        let and = Node::Constrain(Constrain::new(
+            String::from(BooleanFunction::And.symbol()),
+            BooleanFunction::And,
+        ));
+        let and_idx = formula.add_node(and);
+
+        formula.add_edge(lhs, and_idx, ArgumentSide::Lhs);
+        formula.add_edge(*rhs, and_idx, ArgumentSide::Rhs);
+
+        and_idx
+    })
+}
+
+pub fn build_dataflow_graph(
     path: &[Instruction],
+    branch_decisions: &[bool],
     data_segment: &[u8],
     elf_metadata: ElfMetadata,
-) -> Option<(Formula, NodeIndex)> {
-    DataFlowGraphBuilder::new(1000000, path, data_segment, elf_metadata).generate_graph()
+) -> Result<Option<(Formula, Vec<NodeIndex>)>, String> {
+    DataFlowGraphBuilder::new(1000000, path, branch_decisions, data_segment, elf_metadata)
+        .generate_graph()
+}
+
+/// Decode instruction words of a path
+#[cfg(test)]
+pub fn decode_all(words: &[u32]) -> Vec<Instruction> {
+    words
+        .iter()
+        .map(|w| riscv_decode::decode(*w).unwrap())
+        .collect()
+}
+
+/// Metadata of a binary without code, which is loaded at 0x10000
+#[cfg(test)]
+pub fn elf_metadata() -> ElfMetadata {
+    ElfMetadata {
+        entry_address: 0x10000,
+        code_length: 0,
+        code_start: 0x10000,
+        functions: vec![],
+    }
 }
 
 // TODO: need to load data segment  => then write test
@@ -546,6 +796,7 @@ mod tests {
     use crate::cfg;
     use crate::cfg::ControlFlowGraph;
     use crate::dead_code_elimination::eliminate_dead_code;
+    use crate::path::Path as ProgramPath;
     use petgraph::dot::Dot;
     use petgraph::visit::EdgeRef;
     use serial_test::serial;
@@ -557,44 +808,227 @@ mod tests {
 
     // Returns a path of RISC-U instructions and branch decisions (if true or false branch has been taken)
     // for a path with 1 BEQ instruction, the vector of branch decisions has the length of 1
-    pub fn extract_candidate_path(graph: &ControlFlowGraph) -> (Vec<Instruction>, Vec<bool>) {
-        fn next(graph: &ControlFlowGraph, idx: NodeIndex) -> Option<(NodeIndex, Option<bool>)> {
-            let edges = graph.edges(idx);
-
-            if let Some(edge) = edges.last() {
-                let target = edge.target();
-
-                match graph[idx] {
-                    Instruction::Beq(_) => {
-                        let next_idx = edge.target().index();
-
-                        if next_idx == idx.index() + 1 {
-                            Some((target, Some(false)))
-                        } else {
-                            Some((target, Some(true)))
-                        }
-                    }
-                    _ => Some((target, None)),
-                }
-            } else {
-                None
-            }
-        }
-        let mut path = vec![];
-        let mut branch_decisions = vec![];
+    pub fn extract_candidate_path(graph: &ControlFlowGraph) -> ProgramPath {
+        let mut path = ProgramPath::new();
         let mut idx = graph.node_indices().next().unwrap();
-        path.push(idx);
-        while let Some(n) = next(graph, idx) {
-            path.push(n.0);
-            idx = n.0;
-
-            if let Some(branch_decision) = n.1 {
-                branch_decisions.push(branch_decision);
-            }
+        path.push(graph, idx, None);
+        while let Some(edge) = graph.edges(idx).last() {
+            idx = edge.target();
+            path.push(graph, idx, Some(*edge.weight()));
         }
-        let instruction_path = path.iter().map(|idx| graph[*idx]).collect();
 
-        (instruction_path, branch_decisions)
+        path
+    }
+
+    fn operators(formula: &Formula, roots: &[NodeIndex]) -> Vec<BooleanFunction> {
+        roots
+            .iter()
+            .map(|root| match &formula[*root] {
+                Node::Constrain(c) => c.op,
+                _ => panic!("every root is a Constrain node"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn can_encode_branch_decisions_as_path_constraints() {
+        let path = decode_all(&[
+            0x03f00893, // addi a7, zero, 63
+            0x40000593, // addi a1, zero, 1024
+            0x00800613, // addi a2, zero, 8
+            0x00000073, // ecall
+            0x0005b503, // ld a0, 0(a1)
+            0x02a00293, // addi t0, zero, 42
+            0x00550463, // beq a0, t0, 8
+            0x00551463, // bne a0, t0, 8
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+        ]);
+
+        let (formula, roots) =
+            build_dataflow_graph(path.as_slice(), &[false, true], &[], elf_metadata())
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(
+            operators(&formula, roots.as_slice()),
+            vec![
+                BooleanFunction::NotEquals,
+                BooleanFunction::NotEquals,
+                BooleanFunction::GreaterThan
+            ],
+            "path condition and exit code"
+        );
+
+        let mut arguments = formula
+            .edges_directed(roots[0], petgraph::Direction::Incoming)
+            .map(|e| (formula[e.source()].clone(), *e.weight()))
+            .collect::<Vec<_>>();
+        arguments.sort_by_key(|(_, side)| *side == ArgumentSide::Rhs);
+
+        assert!(matches!(arguments[0], (Node::Input(_), ArgumentSide::Lhs)));
+        assert_eq!(
+            arguments[1],
+            (Node::Constant(Const::new(42)), ArgumentSide::Rhs)
+        );
+
+        let infeasible = decode_all(&[
+            0x00100293, // addi t0, zero, 1
+            0x00028463, // beq t0, zero, 8
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+        ]);
+
+        assert!(
+            matches!(
+                build_dataflow_graph(infeasible.as_slice(), &[true], &[], elf_metadata()),
+                Ok(None)
+            ),
+            "concrete branch 1 == 0 can not be taken"
+        );
+        assert!(
+            build_dataflow_graph(path.as_slice(), &[false], &[], elf_metadata()).is_err(),
+            "decision of the second branch is missing"
+        );
+        assert!(
+            build_dataflow_graph(path.as_slice(), &[false, true, true], &[], elf_metadata())
+                .is_err(),
+            "there is no third branch"
+        );
+    }
+
+    #[test]
+    fn can_constrain_divisor_of_division_target_to_zero() {
+        // a = 1 / (input - 48)
+        let path = decode_all(&[
+            0x03f00893, // addi a7, zero, 63
+            0x40000593, // addi a1, zero, 1024
+            0x00100613, // addi a2, zero, 1
+            0x00000073, // ecall
+            0x0005b503, // ld a0, 0(a1)
+            0xfd050513, // addi a0, a0, -48
+            0x00100293, // addi t0, zero, 1
+            0x02a2d533, // divu a0, t0, a0
+        ]);
+
+        let (formula, roots) = build_dataflow_graph(path.as_slice(), &[], &[], elf_metadata())
+            .unwrap()
+            .unwrap();
+        let root = *roots.last().unwrap();
+
+        assert_eq!(
+            operators(&formula, roots.as_slice()),
+            vec![BooleanFunction::Equals]
+        );
+
+        let mut arguments = formula
+            .edges_directed(root, petgraph::Direction::Incoming)
+            .map(|e| (formula[e.source()].clone(), *e.weight()))
+            .collect::<Vec<_>>();
+        arguments.sort_by_key(|(_, side)| *side == ArgumentSide::Rhs);
+
+        assert!(matches!(
+            arguments[0],
+            (Node::Instruction(_), ArgumentSide::Lhs)
+        ));
+        assert_eq!(
+            arguments[1],
+            (Node::Constant(Const::new(0)), ArgumentSide::Rhs)
+        );
+
+        let concrete = decode_all(&[
+            0x00100293, // addi t0, zero, 1
+            0x02a2d533, // divu a0, t0, a0
+            0x02a2f533, // remu a0, t0, a0
+        ]);
+
+        assert!(
+            matches!(
+                build_dataflow_graph(&concrete[..2], &[], &[], elf_metadata()),
+                Ok(Some(_))
+            ),
+            "a0 is zero"
+        );
+        assert!(
+            matches!(
+                build_dataflow_graph(concrete.as_slice(), &[], &[], elf_metadata()),
+                Ok(None)
+            ),
+            "divu by zero sets a0 to u64::MAX"
+        );
+    }
+
+    #[test]
+    fn can_reject_unsupported_paths() {
+        let symbolic_address = decode_all(&[
+            0x03f00893, // addi a7, zero, 63
+            0x40000593, // addi a1, zero, 1024
+            0x00800613, // addi a2, zero, 8
+            0x00000073, // ecall
+            0x0005b503, // ld a0, 0(a1)
+            0x00053283, // ld t0, 0(a0)
+        ]);
+
+        assert!(
+            build_dataflow_graph(symbolic_address.as_slice(), &[], &[], elf_metadata()).is_err()
+        );
+        assert!(
+            build_dataflow_graph(&decode_all(&[0x00000073]), &[], &[], elf_metadata()).is_err(),
+            "syscall 0 is not supported"
+        );
+        assert!(
+            build_dataflow_graph(&decode_all(&[0x00100073]), &[], &[], elf_metadata()).is_err(),
+            "ebreak is not supported"
+        );
+    }
+
+    #[test]
+    fn can_combine_orderings_of_branches_into_one_root() {
+        let path = decode_all(&[
+            0x03f00893, // addi a7, zero, 63
+            0x40000593, // addi a1, zero, 1024
+            0x00800613, // addi a2, zero, 8
+            0x00000073, // ecall
+            0x0005b503, // ld a0, 0(a1)
+            0x02a00293, // addi t0, zero, 42
+            0x00554463, // blt a0, t0, 8
+            0x00a2f463, // bgeu t0, a0, 8
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+        ]);
+
+        let (mut formula, roots) =
+            build_dataflow_graph(path.as_slice(), &[false, true], &[], elf_metadata())
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(
+            operators(&formula, roots.as_slice()),
+            vec![
+                BooleanFunction::SignedGreaterThanOrEqual,
+                BooleanFunction::GreaterThanOrEqual,
+                BooleanFunction::GreaterThan
+            ]
+        );
+
+        let root = conjunction(&mut formula, roots.as_slice());
+
+        assert_eq!(operators(&formula, &[root]), vec![BooleanFunction::And]);
+        assert_eq!(
+            eliminate_dead_code(&formula, root)
+                .raw_nodes()
+                .iter()
+                .filter(|n| matches!(n.weight, Node::Constrain(_)))
+                .count(),
+            5,
+            "every root is reachable from the conjunction"
+        );
+
+        assert!(BooleanFunction::SignedLessThan.evaluate(u64::MAX, 0));
+        assert!(!BooleanFunction::LessThan.evaluate(u64::MAX, 0));
+        assert!(BooleanFunction::Not.evaluate(0, 0));
+        assert!(!BooleanFunction::And.evaluate(1, 0));
+        assert!(BooleanFunction::Or.evaluate(1, 0));
     }
 
     // TODO: write a unit test without dependency on selfie and external files
@@ -618,18 +1052,27 @@ mod tests {
 
         let test_file = Path::new("symbolic/symbolic-exit.riscu.o");
 
-        let (graph, data_segment, elf_metadata) = cfg::build_from_file(test_file).unwrap();
+        let (graph, data_segment, elf_metadata) =
+            cfg::build_from_file(test_file, cfg::Strategy::LinearSweep).unwrap();
 
         println!("{:?}", data_segment);
 
-        let (path, _branch_decisions) = extract_candidate_path(&graph);
+        let path = extract_candidate_path(&graph);
+
+        println!("{}", path);
 
-        println!("{:?}", path);
+        let (mut formula, roots) = build_dataflow_graph(
+            path.instructions().as_slice(),
+            path.branch_decisions().as_slice(),
+            data_segment.as_slice(),
+            elf_metadata,
+        )
+        .unwrap()
+        .unwrap();
 
-        let (formula, _root) =
-            build_dataflow_graph(&path, data_segment.as_slice(), elf_metadata).unwrap();
+        let root = conjunction(&mut formula, roots.as_slice());
 
-        let graph_wo_dc = eliminate_dead_code(&formula, _root);
+        let graph_wo_dc = eliminate_dead_code(&formula, root);
 
         let dot_graph = Dot::with_config(&graph_wo_dc, &[]);
 
@@ -1,7 +1,10 @@
 use std::fmt::Display;
 use std::path::Path;
 
+mod basic_block;
 mod bitvec;
+mod cache;
+mod call_graph;
 mod candidate_path;
 mod cfg;
 mod cli;
@@ -9,14 +12,32 @@ mod compile;
 mod dead_code_elimination;
 mod decode;
 mod disassemble;
+mod dominance;
+mod dot;
 mod elf;
 mod engine;
+mod export;
 mod formula_graph;
 mod iterator;
+mod path;
+mod path_enumeration;
+mod reach;
+mod search;
+mod smt;
+mod stats;
+mod supergraph;
 mod ternary;
+mod value_analysis;
 
+use basic_block::BasicBlocks;
+use call_graph::CallGraph;
+use candidate_path::{find_bug_targets, find_roots, BugClass};
+use cfg::Strategy;
 use compile::compile_example;
 use disassemble::disassemble_riscu;
+use formula_graph::build_dataflow_graph;
+use search::{PathSearch, SearchStrategy};
+use stats::Statistics;
 
 fn main() {
     let matches = cli::args().get_matches();
@@ -54,23 +75,344 @@ fn main() {
                 let input = Path::new(cfg_args.value_of("input-file").unwrap());
                 let output = Path::new(cfg_args.value_of("output-file").unwrap());
 
-                let (graph, _, _) = cfg::build_from_file(Path::new(input))?;
+                let strategy = cli::strategy(cfg_args);
 
-                if let Some(_format @ "png") = cfg_args.value_of("format") {
-                    let tmp = Path::new(".tmp-cfg.dot");
+                let (graph, _, elf_metadata) = cfg::build_from_file(Path::new(input), strategy)?;
 
-                    cfg::write_to_file(&graph, tmp).map_err(|e| e.to_string())?;
+                cfg::find_unresolved_indirect_jumps(&graph)
+                    .iter()
+                    .for_each(|idx| {
+                        eprintln!(
+                            "warning: unresolved indirect jump at {:#x}",
+                            graph[*idx].address
+                        )
+                    });
 
-                    cfg::convert_dot_to_png(tmp, output)?;
+                if strategy == Strategy::RecursiveTraversal {
+                    let unreached = cfg::find_unreached_ranges(
+                        &graph,
+                        elf_metadata.code_start,
+                        elf_metadata.code_length,
+                    );
 
-                    std::fs::remove_file(tmp).map_err(|e| e.to_string())?;
-                } else {
-                    cfg::write_to_file(&graph, output).map_err(|e| e.to_string())?;
+                    unreached.iter().for_each(|(start, end)| {
+                        println!(
+                            "unreached: {:#x}..{:#x} ({} bytes)",
+                            start,
+                            end,
+                            end - start
+                        )
+                    });
+
+                    println!(
+                        "unreached bytes: {}",
+                        unreached
+                            .iter()
+                            .map(|(start, end)| end - start)
+                            .sum::<u64>()
+                    );
+                }
+
+                if cfg_args.is_present("stats") {
+                    let call_graph = CallGraph::build(&graph, &elf_metadata);
+                    let stats = Statistics::compute(&graph, &call_graph, &elf_metadata);
+
+                    match cfg_args.value_of("format") {
+                        Some("json") => println!("{}", stats.to_json()),
+                        _ => println!("{}", stats.to_text()),
+                    }
+
+                    return Ok(());
+                }
+
+                let granularity = cfg_args.value_of("granularity");
+                let annotate = cfg_args.is_present("annotate");
+
+                if annotate && granularity != Some("instruction") {
+                    return Err(String::from(
+                        "annotations are only available with instruction granularity",
+                    ));
+                }
+
+                let function = |call_graph: &CallGraph, function: &str| {
+                    match cli::parse_address(function) {
+                        Ok(address) => call_graph.function_at_address(&graph, address),
+                        Err(_) => call_graph.function_by_name(function),
+                    }
+                    .ok_or_else(|| format!("function {} does not exist", function))
+                };
+
+                let write_to_file = |file: &Path| -> Result<(), String> {
+                    match (granularity, cfg_args.value_of("function")) {
+                        (Some("instruction"), Some(name)) => {
+                            let call_graph = CallGraph::build(&graph, &elf_metadata);
+                            let function = function(&call_graph, name)?;
+                            let function_cfg = call_graph.function_cfg(&graph, function);
+
+                            if annotate {
+                                dominance::write_annotated_function_to_file(&function_cfg, file)
+                            } else {
+                                call_graph::write_function_to_file(
+                                    &function_cfg,
+                                    &call_graph.graph[function].name,
+                                    file,
+                                )
+                            }
+                        }
+                        (Some("block"), Some(name)) => {
+                            let call_graph = CallGraph::build(&graph, &elf_metadata);
+                            let function = function(&call_graph, name)?;
+                            let function_cfg = call_graph.function_cfg(&graph, function);
+                            let blocks = BasicBlocks::build(&function_cfg.graph);
+                            let name = &call_graph.graph[function].name;
+
+                            basic_block::write_to_file(
+                                &blocks,
+                                &function_cfg.graph,
+                                |_| Some((0, name.clone())),
+                                file,
+                            )
+                        }
+                        (_, Some(_)) => {
+                            return Err(String::from(
+                                "single functions can not be generated as call graph",
+                            ))
+                        }
+                        (Some("block"), None) => {
+                            let call_graph = CallGraph::build(&graph, &elf_metadata);
+                            let blocks = BasicBlocks::build(&graph);
+
+                            basic_block::write_to_file(
+                                &blocks,
+                                &graph,
+                                |idx| dot::function_cluster(&call_graph, idx),
+                                file,
+                            )
+                        }
+                        (Some("call-graph"), None) => {
+                            let call_graph = CallGraph::build(&graph, &elf_metadata);
+
+                            call_graph::write_to_file(&call_graph, &graph, file)
+                        }
+                        _ if annotate => {
+                            let call_graph = CallGraph::build(&graph, &elf_metadata);
+
+                            dominance::write_annotated_to_file(&graph, &call_graph, file)
+                        }
+                        _ => {
+                            let call_graph = CallGraph::build(&graph, &elf_metadata);
+                            let bug_targets =
+                                find_roots(&graph, &elf_metadata).into_iter().collect();
+
+                            dot::write_to_file(&graph, Some(&call_graph), &bug_targets, file)
+                        }
+                    }
+                    .map_err(|e| e.to_string())
+                };
+
+                match cfg_args.value_of("format") {
+                    Some(format @ "png") | Some(format @ "svg") | Some(format @ "pdf") => {
+                        let tmp = Path::new(".tmp-cfg.dot");
+
+                        write_to_file(tmp)?;
+
+                        dot::convert_dot(tmp, output, format)?;
+
+                        std::fs::remove_file(tmp).map_err(|e| e.to_string())?;
+                    }
+                    Some(format @ "json") | Some(format @ "graphml") | Some(format @ "mermaid") => {
+                        if granularity != Some("instruction") || annotate {
+                            return Err(format!(
+                                "{} is only available with instruction granularity and without annotations",
+                                format
+                            ));
+                        }
+
+                        let function_cfg = match cfg_args.value_of("function") {
+                            Some(name) => {
+                                let call_graph = CallGraph::build(&graph, &elf_metadata);
+
+                                Some(call_graph.function_cfg(&graph, function(&call_graph, name)?))
+                            }
+                            None => None,
+                        };
+                        let graph = function_cfg.as_ref().map_or(&graph, |f| &f.graph);
+
+                        match format {
+                            "json" => export::write_json(graph, output),
+                            "graphml" => export::write_graphml(graph, output),
+                            _ => export::write_mermaid(graph, output),
+                        }
+                        .map_err(|e| e.to_string())?;
+                    }
+                    _ => write_to_file(output)?,
                 }
 
                 Ok(())
             });
         }
+        ("reach", Some(reach_args)) => handle_error(|| -> Result<(), String> {
+            let input = Path::new(reach_args.value_of("input-file").unwrap());
+
+            let strategy = cli::strategy(reach_args);
+
+            let (graph, _, elf_metadata) = cfg::build_from_file(input, strategy)?;
+            let call_graph = CallGraph::build(&graph, &elf_metadata);
+
+            let address =
+                reach::resolve_target(reach_args.value_of("target").unwrap(), &elf_metadata)?;
+            let reachability = reach::query(&graph, &call_graph, &elf_metadata, address)?;
+
+            println!(
+                "{:?} is {} from entry {:#x}",
+                graph[reachability.target],
+                if reachability.reachable {
+                    "statically reachable"
+                } else {
+                    "unreachable"
+                },
+                elf_metadata.entry_address
+            );
+
+            println!(
+                "predecessor functions: {}",
+                reachability
+                    .functions
+                    .iter()
+                    .map(|f| call_graph.graph[*f].name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+
+            Ok(())
+        }),
+        ("paths", Some(paths_args)) => handle_error(|| -> Result<(), String> {
+            let input = Path::new(paths_args.value_of("input-file").unwrap());
+
+            let strategy = cli::strategy(paths_args);
+
+            let number = |name: &str| cli::parse_number(name, paths_args.value_of(name).unwrap());
+
+            let search = match paths_args.value_of("search") {
+                Some("depth-first") => SearchStrategy::DepthFirst,
+                Some("random") => SearchStrategy::Random {
+                    seed: number("seed")?,
+                },
+                Some("uncovered-branch-first") => SearchStrategy::UncoveredBranchFirst,
+                _ => SearchStrategy::ShortestFirst,
+            };
+
+            let (graph, _, elf_metadata) = cfg::build_from_file(input, strategy)?;
+
+            let entry = cfg::node_at(&graph, elf_metadata.entry_address).ok_or_else(|| {
+                format!(
+                    "there is no instruction at entry {:#x}",
+                    elf_metadata.entry_address
+                )
+            })?;
+
+            let bug_targets = find_bug_targets(&graph, &elf_metadata);
+
+            PathSearch::new(
+                &graph,
+                entry,
+                find_roots(&graph, &elf_metadata).as_slice(),
+                number("loop-bound")? as usize,
+                number("call-depth")? as usize,
+                search,
+            )
+            .take(number("limit")? as usize)
+            .enumerate()
+            .for_each(|(i, path)| {
+                let root = path.nodes().last().copied();
+
+                println!(
+                    "path {} to {:#x} ({}, {} instructions):\n{}",
+                    i,
+                    path.addresses().last().unwrap(),
+                    bug_targets
+                        .iter()
+                        .filter(|target| Some(target.node) == root)
+                        .map(|target| target.class.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    path.len(),
+                    path
+                )
+            });
+
+            Ok(())
+        }),
+        ("smt", Some(smt_args)) => handle_error(|| -> Result<(), String> {
+            let input = Path::new(smt_args.value_of("input-file").unwrap());
+            let output = Path::new(smt_args.value_of("output-file").unwrap());
+
+            let strategy = cli::strategy(smt_args);
+
+            let number = |name: &str| cli::parse_number(name, smt_args.value_of(name).unwrap());
+
+            let (graph, data_segment, elf_metadata) = cfg::build_from_file(input, strategy)?;
+
+            let entry = cfg::node_at(&graph, elf_metadata.entry_address).ok_or_else(|| {
+                format!(
+                    "there is no instruction at entry {:#x}",
+                    elf_metadata.entry_address
+                )
+            })?;
+
+            // formulas only encode the bug conditions of exits and divisions
+            let mut roots = find_bug_targets(&graph, &elf_metadata)
+                .into_iter()
+                .filter(|target| {
+                    matches!(
+                        target.class,
+                        BugClass::ExitCode | BugClass::DivisionByZero | BugClass::RemainderByZero
+                    )
+                })
+                .map(|target| target.node)
+                .collect::<Vec<_>>();
+
+            roots.dedup();
+
+            let (path, formula, roots) = PathSearch::new(
+                &graph,
+                entry,
+                roots.as_slice(),
+                number("loop-bound")? as usize,
+                number("call-depth")? as usize,
+                SearchStrategy::ShortestFirst,
+            )
+            .find_map(|path| {
+                match build_dataflow_graph(
+                    path.instructions().as_slice(),
+                    path.branch_decisions().as_slice(),
+                    data_segment.as_slice(),
+                    elf_metadata.clone(),
+                ) {
+                    Ok(formula) => formula.map(|(formula, roots)| (path, formula, roots)),
+                    Err(error) => {
+                        eprintln!(
+                            "warning: skipping path to {:#x}: {}",
+                            path.addresses().last().unwrap(),
+                            error
+                        );
+
+                        None
+                    }
+                }
+            })
+            .ok_or_else(|| String::from("no candidate path yields a formula"))?;
+
+            smt::write_to_file(&formula, roots.as_slice(), output)?;
+
+            println!(
+                "formula of path to {:#x} written to {}",
+                path.addresses().last().unwrap(),
+                output.display()
+            );
+
+            Ok(())
+        }),
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,213 @@
+//! # Handle program paths
+//!
+//! A `Path` is a sequence of instructions of a `ControlFlowGraph`. Every step
+//! records the `NodeIndex`, virtual address and instruction, and for `beq`
+//! the branch decision (`true` if the branch is taken).
+//!
+//! Paths are created from walks over the CFG (see `path_enumeration`) or
+//! from recorded concrete traces (virtual addresses of executed
+//! instructions). A path is serialized as its trace, one address per line.
+
+use crate::cfg::{node_at, ControlFlowGraph, EdgeType};
+use crate::cli::parse_address;
+use crate::disassemble::disassemble_instruction;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use riscv_decode::Instruction;
+use std::fmt;
+
+/// A single instruction of a `Path`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    pub node: NodeIndex,
+    pub address: u64,
+    pub instruction: Instruction,
+    /// Branch decision of a `beq` (`None` for all other instructions and for
+    /// the last instruction of a path)
+    pub decision: Option<bool>,
+}
+
+/// Sequence of instructions of a `ControlFlowGraph`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Path {
+    steps: Vec<Step>,
+}
+
+#[allow(dead_code)]
+impl Path {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `node` of `cfg`, which is reached from the last instruction
+    /// over an edge of kind `edge_type`.
+    pub fn push(&mut self, cfg: &ControlFlowGraph, node: NodeIndex, edge_type: Option<EdgeType>) {
+        if let Some(last) = self.steps.last_mut() {
+            last.decision = edge_type.and_then(|e| e.branch_decision());
+        }
+
+        self.steps.push(Step {
+            node,
+            address: cfg[node].address,
+            instruction: cfg[node].instruction,
+            decision: None,
+        });
+    }
+
+    /// Create the path of a walk over `nodes` of `cfg`.
+    pub fn from_walk(cfg: &ControlFlowGraph, nodes: &[NodeIndex]) -> Result<Self, String> {
+        let mut path = Self::new();
+
+        for (i, node) in nodes.iter().enumerate() {
+            let edge_type = match i.checked_sub(1).map(|p| nodes[p]) {
+                Some(previous) => Some(
+                    cfg.edges(previous)
+                        .find(|e| e.target() == *node)
+                        .map(|e| *e.weight())
+                        .ok_or_else(|| {
+                            format!(
+                                "there is no edge from {:#x} to {:#x}",
+                                cfg[previous].address, cfg[*node].address
+                            )
+                        })?,
+                ),
+                None => None,
+            };
+
+            path.push(cfg, *node, edge_type);
+        }
+
+        Ok(path)
+    }
+
+    /// Create the path of a concrete trace of virtual `addresses`.
+    pub fn from_trace(cfg: &ControlFlowGraph, addresses: &[u64]) -> Result<Self, String> {
+        let nodes = addresses
+            .iter()
+            .map(|a| node_at(cfg, *a).ok_or_else(|| format!("there is no instruction at {:#x}", a)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_walk(cfg, nodes.as_slice())
+    }
+
+    /// Serialize the path as trace (one hexadecimal address per line).
+    pub fn serialize(&self) -> String {
+        self.steps
+            .iter()
+            .map(|s| format!("{:#x}\n", s.address))
+            .collect()
+    }
+
+    /// Deserialize a path of `cfg` from a trace (empty lines are ignored).
+    pub fn deserialize(cfg: &ControlFlowGraph, trace: &str) -> Result<Self, String> {
+        let addresses = trace
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(parse_address)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_trace(cfg, addresses.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Step> {
+        self.steps.iter()
+    }
+
+    pub fn nodes(&self) -> Vec<NodeIndex> {
+        self.steps.iter().map(|s| s.node).collect()
+    }
+
+    pub fn addresses(&self) -> Vec<u64> {
+        self.steps.iter().map(|s| s.address).collect()
+    }
+
+    pub fn instructions(&self) -> Vec<Instruction> {
+        self.steps.iter().map(|s| s.instruction).collect()
+    }
+
+    /// Get the decisions of all branches in path order.
+    pub fn branch_decisions(&self) -> Vec<bool> {
+        self.steps.iter().filter_map(|s| s.decision).collect()
+    }
+}
+
+impl<'a> IntoIterator for &'a Path {
+    type Item = &'a Step;
+    type IntoIter = std::slice::Iter<'a, Step>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.steps.iter()
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in self {
+            write!(
+                f,
+                "{:#x}: {}",
+                step.address,
+                disassemble_instruction(step.instruction)
+            )?;
+
+            match step.decision {
+                Some(true) => writeln!(f, " (taken)")?,
+                Some(false) => writeln!(f, " (not taken)")?,
+                None => writeln!(f)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+
+    #[test]
+    fn can_convert_traces_to_paths() {
+        let graph = cfg::build(
+            assemble(&[
+                0x00100513, // addi a0, zero, 1
+                0x00050463, // beq a0, zero, 8
+                0x00000013, // nop
+                0x05d00893, // addi a7, zero, 93
+                0x00000073, // ecall
+            ])
+            .as_slice(),
+            0x10000,
+        );
+
+        let path = Path::from_trace(&graph, &[0x10000, 0x10004, 0x10008, 0x1000c]).unwrap();
+
+        assert_eq!(path.len(), 4);
+        assert_eq!(path.nodes(), (0..4).map(NodeIndex::new).collect::<Vec<_>>());
+        assert_eq!(path.branch_decisions(), vec![false]);
+        assert_eq!(
+            path.to_string(),
+            "0x10000: addi a0,zero,1\n\
+             0x10004: beq a0,zero,8 (not taken)\n\
+             0x10008: nop\n\
+             0x1000c: addi a7,zero,93\n"
+        );
+
+        assert_eq!(Path::deserialize(&graph, &path.serialize()), Ok(path));
+
+        let taken = Path::from_walk(&graph, &[NodeIndex::new(1), NodeIndex::new(3)]).unwrap();
+
+        assert_eq!(taken.branch_decisions(), vec![true]);
+        assert!(Path::from_trace(&graph, &[0x10000, 0x10008]).is_err());
+        assert!(Path::from_trace(&graph, &[0x20000]).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! # Handle bounded enumeration of program paths
+//!
+//! A `PathEnumerator` lazily yields every concrete instruction `Path` from an
+//! entry to a target instruction. Paths only follow matched calls and returns (see
+//! `supergraph`) and are bounded, so that enumeration always terminates:
+//! - an instruction occurs at most `loop_bound` times in the same calling
+//!   context on a path (loops are unrolled at most `loop_bound` times)
+//! - calls are followed up to a depth of `max_call_depth` (deeper paths are
+//!   dropped)
+//!
+//! The instructions of a path and its branch decisions are the input of
+//! `formula_graph::build_dataflow_graph`.
+
+use crate::cfg::{ControlFlowGraph, EdgeType};
+use crate::path::Path;
+use crate::supergraph::{CallString, Context, Supergraph};
+use petgraph::graph::NodeIndex;
+use std::collections::HashMap;
+
+/// Instruction on the current path and its unexplored successors
+struct Frame {
+    context: Context,
+    /// Kind of the edge leading to this instruction
+    edge_type: Option<EdgeType>,
+    successors: Vec<(Context, EdgeType)>,
+}
+
+/// Depth first enumeration of all bounded paths from entry to target
+#[allow(dead_code)]
+pub struct PathEnumerator<'a> {
+    cfg: &'a ControlFlowGraph,
+    supergraph: Supergraph<'a>,
+    target: NodeIndex,
+    loop_bound: usize,
+    max_call_depth: usize,
+    /// Entry, as long as enumeration has not been started
+    start: Option<Context>,
+    stack: Vec<Frame>,
+    visits: HashMap<Context, usize>,
+}
+
+#[allow(dead_code)]
+impl<'a> PathEnumerator<'a> {
+    /// Enumerate the paths of `cfg` from `entry` to `target`.
+    pub fn new(
+        cfg: &'a ControlFlowGraph,
+        entry: NodeIndex,
+        target: NodeIndex,
+        loop_bound: usize,
+        max_call_depth: usize,
+    ) -> Self {
+        Self {
+            cfg,
+            supergraph: Supergraph::new(cfg, max_call_depth),
+            target,
+            loop_bound,
+            max_call_depth,
+            start: Some(Context {
+                node: entry,
+                call_string: CallString::empty(),
+            }),
+            stack: vec![],
+            visits: HashMap::new(),
+        }
+    }
+
+    /// Append `context` to the current path, if the bounds allow it.
+    fn push(&mut self, context: Context, edge_type: Option<EdgeType>) -> bool {
+        let visits = self.visits.entry(context.clone()).or_insert(0);
+
+        if *visits >= self.loop_bound {
+            return false;
+        }
+
+        *visits += 1;
+
+        let mut successors = if context.node == self.target {
+            vec![]
+        } else {
+            self.supergraph.successors(&context)
+        };
+
+        // successors are popped, hence explore them in CFG order
+        successors.reverse();
+
+        self.stack.push(Frame {
+            context,
+            edge_type,
+            successors,
+        });
+
+        true
+    }
+
+    fn pop(&mut self) {
+        if let Some(frame) = self.stack.pop() {
+            if let Some(visits) = self.visits.get_mut(&frame.context) {
+                *visits -= 1;
+            }
+        }
+    }
+
+    fn current_path(&self) -> Path {
+        let mut path = Path::new();
+
+        self.stack
+            .iter()
+            .for_each(|f| path.push(self.cfg, f.context.node, f.edge_type));
+
+        path
+    }
+}
+
+impl<'a> Iterator for PathEnumerator<'a> {
+    type Item = Path;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            if self.push(start, None) && self.stack[0].context.node == self.target {
+                return Some(self.current_path());
+            }
+        }
+
+        loop {
+            let frame = self.stack.last_mut()?;
+
+            match frame.successors.pop() {
+                Some((_, EdgeType::Call))
+                    if frame.context.call_string.call_sites().len() >= self.max_call_depth => {}
+                Some((context, edge_type)) => {
+                    if self.push(context, Some(edge_type))
+                        && self.stack.last().unwrap().context.node == self.target
+                    {
+                        return Some(self.current_path());
+                    }
+                }
+                None => self.pop(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+
+    fn nodes(indices: &[usize]) -> Vec<NodeIndex> {
+        indices.iter().copied().map(NodeIndex::new).collect()
+    }
+
+    #[test]
+    fn can_unroll_loops_up_to_bound() {
+        let graph = cfg::build(
+            assemble(&[
+                0x00200513, // addi a0, zero, 2
+                0xfff50513, // loop: addi a0, a0, -1
+                0x00050463, // beq a0, zero, 8
+                0xff9ff06f, // jal zero, loop
+                0x05d00893, // addi a7, zero, 93
+                0x00000073, // ecall
+            ])
+            .as_slice(),
+            0,
+        );
+
+        let paths = |loop_bound| {
+            PathEnumerator::new(&graph, NodeIndex::new(0), NodeIndex::new(5), loop_bound, 0)
+                .map(|path| (path.nodes(), path.branch_decisions()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(1), vec![(nodes(&[0, 1, 2, 4, 5]), vec![true])]);
+        assert_eq!(
+            paths(2),
+            vec![
+                (nodes(&[0, 1, 2, 4, 5]), vec![true]),
+                (nodes(&[0, 1, 2, 3, 1, 2, 4, 5]), vec![false, true]),
+            ]
+        );
+        assert_eq!(paths(3).len(), 3);
+    }
+
+    #[test]
+    fn can_limit_call_depth() {
+        let graph = cfg::build(
+            assemble(&[
+                0x00c000ef, // jal ra, 12
+                0x05d00893, // addi a7, zero, 93
+                0x00000073, // ecall
+                0x00008067, // jalr zero, 0(ra)
+            ])
+            .as_slice(),
+            0,
+        );
+
+        let paths = |max_call_depth| {
+            PathEnumerator::new(
+                &graph,
+                NodeIndex::new(0),
+                NodeIndex::new(2),
+                1,
+                max_call_depth,
+            )
+            .map(|path| path.nodes())
+            .collect::<Vec<_>>()
+        };
+
+        assert!(paths(0).is_empty());
+        assert_eq!(paths(1), vec![nodes(&[0, 3, 1, 2])]);
+    }
+}
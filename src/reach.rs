@@ -0,0 +1,151 @@
+//! # Handle reachability queries for program points
+//!
+//! A target is named by a virtual address or by a symbol with an optional
+//! offset (`main+0x10`). It is statically reachable, if there is a valid path
+//! (see `supergraph`) from the program entry to the target. The predecessor
+//! functions of a target are all functions, which contain the target or
+//! (transitively) call one of those functions.
+//!
+//! There is no solver in the pipeline yet, hence a query does not compute a
+//! concrete input reaching the target.
+
+use crate::call_graph::CallGraph;
+use crate::cfg::{node_at, ControlFlowGraph};
+use crate::cli::parse_address;
+use crate::elf::ElfMetadata;
+use crate::supergraph::{CallString, Supergraph};
+use petgraph::graph::NodeIndex;
+use std::collections::BTreeSet;
+
+/// Result of a reachability query
+#[derive(Debug)]
+pub struct Reachability {
+    /// Instruction at the target address
+    pub target: NodeIndex,
+    /// `true` if the target is reachable from the entry on a valid path
+    pub reachable: bool,
+    /// Functions containing the target and their (transitive) callers
+    pub functions: Vec<NodeIndex>,
+}
+
+/// Resolve `target` (`ADDRESS` or `SYMBOL[+OFFSET]`) to a virtual address.
+pub fn resolve_target(target: &str, elf_metadata: &ElfMetadata) -> Result<u64, String> {
+    if let Ok(address) = parse_address(target) {
+        return Ok(address);
+    }
+
+    let (name, offset) = match target.find('+') {
+        Some(split) => (&target[..split], parse_address(&target[split + 1..])?),
+        None => (target, 0),
+    };
+
+    elf_metadata
+        .functions
+        .iter()
+        .find(|symbol| symbol.name == name)
+        .map(|symbol| symbol.address + offset)
+        .ok_or_else(|| format!("symbol {} does not exist", name))
+}
+
+/// Get all functions containing `target` and all their (transitive) callers.
+fn predecessor_functions(call_graph: &CallGraph, target: NodeIndex) -> Vec<NodeIndex> {
+    let mut functions = BTreeSet::new();
+    let mut worklist = call_graph.functions_containing(target);
+
+    while let Some(function) = worklist.pop() {
+        if functions.insert(function) {
+            worklist.extend(call_graph.callers(function));
+        }
+    }
+
+    functions.into_iter().collect()
+}
+
+/// Check if the instruction at virtual `address` is reachable from the entry
+/// of the program.
+pub fn query(
+    graph: &ControlFlowGraph,
+    call_graph: &CallGraph,
+    elf_metadata: &ElfMetadata,
+    address: u64,
+) -> Result<Reachability, String> {
+    let target = node_at(graph, address)
+        .ok_or_else(|| format!("there is no instruction at {:#x}", address))?;
+
+    let entry = node_at(graph, elf_metadata.entry_address).ok_or_else(|| {
+        format!(
+            "there is no instruction at entry {:#x}",
+            elf_metadata.entry_address
+        )
+    })?;
+
+    // calls are summarized, hence the call string length is irrelevant, and
+    // there is no call pending at the entry
+    let reachable = Supergraph::new(graph, 0).is_reachable(entry, target, &CallString::empty());
+
+    Ok(Reachability {
+        target,
+        reachable,
+        functions: predecessor_functions(call_graph, target),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+    use crate::elf::Symbol;
+
+    #[test]
+    fn can_query_reachability_of_symbols() {
+        // main calls f and exits, g calls f but is never called
+        let graph = cfg::build(
+            assemble(&[
+                0x014000ef, // main: jal ra, 20
+                0x05d00893, // addi a7, zero, 93
+                0x00000073, // ecall
+                0x008000ef, // g: jal ra, 8
+                0x00008067, // jalr zero, 0(ra)
+                0x00100513, // f: addi a0, zero, 1
+                0x00008067, // jalr zero, 0(ra)
+            ])
+            .as_slice(),
+            0x10000,
+        );
+        let symbol = |name: &str, address: u64| Symbol {
+            name: String::from(name),
+            address,
+            size: 0,
+        };
+        let elf_metadata = ElfMetadata {
+            entry_address: 0x10000,
+            code_length: 28,
+            code_start: 0x10000,
+            functions: vec![
+                symbol("main", 0x10000),
+                symbol("g", 0x1000c),
+                symbol("f", 0x10014),
+            ],
+        };
+        let call_graph = CallGraph::build(&graph, &elf_metadata);
+
+        assert_eq!(resolve_target("f+0x4", &elf_metadata), Ok(0x10018));
+        assert_eq!(resolve_target("65560", &elf_metadata), Ok(0x10018));
+        assert!(resolve_target("h", &elf_metadata).is_err());
+
+        let f = query(&graph, &call_graph, &elf_metadata, 0x10018).unwrap();
+        let names = f
+            .functions
+            .iter()
+            .map(|function| call_graph.graph[*function].name.as_str())
+            .collect::<BTreeSet<_>>();
+
+        assert!(f.reachable);
+        assert_eq!(names, vec!["f", "g", "main"].into_iter().collect());
+
+        let g = query(&graph, &call_graph, &elf_metadata, 0x10010).unwrap();
+
+        assert!(!g.reachable, "g is never called");
+        assert!(query(&graph, &call_graph, &elf_metadata, 0x20000).is_err());
+    }
+}
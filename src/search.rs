@@ -0,0 +1,452 @@
+//! # Handle search strategies for candidate paths
+//!
+//! A `PathSearch` selects candidate paths from the entry to a set of roots
+//! (see `candidate_path::find_roots`). Candidates are the bounded paths of
+//! `path_enumeration` (same loop and call depth bounds), but they are never
+//! enumerated up front: the search keeps a frontier of partial paths, which
+//! share their prefixes, and a `SearchStrategy` decides which partial path is
+//! extended next:
+//! - `ShortestFirst`: fewest instructions first (best first search, which
+//!   estimates the remaining length by the distance to the nearest root)
+//! - `DepthFirst`: successors in CFG order
+//! - `Random`: depth first with successors in random order, reproducible by
+//!   a seed
+//! - `UncoveredBranchFirst`: most branch decisions, which are not covered by
+//!   previously selected paths, first
+//!
+//! Partial paths, from which no root is reachable, are dropped. Ties are
+//! always broken by the order of discovery, so that every search is
+//! deterministic.
+
+use crate::cfg::{ControlFlowGraph, EdgeType};
+use crate::path::Path;
+use crate::supergraph::{CallString, Context, Supergraph};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+/// Strategy to select the next candidate path
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SearchStrategy {
+    ShortestFirst,
+    DepthFirst,
+    Random { seed: u64 },
+    UncoveredBranchFirst,
+}
+
+/// Pseudo random number generator (xorshift64*), which is reproducible
+/// across platforms
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        // the state of xorshift must not be zero
+        match seed ^ 0x9e37_79b9_7f4a_7c15 {
+            0 => Self(0x9e37_79b9_7f4a_7c15),
+            state => Self(state),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Get a number in `0..bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Path from the entry, which shares its prefix with other partial paths
+struct Partial {
+    context: Context,
+    /// Kind of the edge leading to this instruction
+    edge_type: Option<EdgeType>,
+    len: usize,
+    parent: Option<Rc<Partial>>,
+}
+
+impl Partial {
+    /// Iterate over all instructions of this path, last one first.
+    fn iter(&self) -> impl Iterator<Item = &Partial> {
+        std::iter::successors(Some(self), |p| p.parent.as_deref())
+    }
+
+    /// Get all branch decisions of this path.
+    fn decisions(&self) -> HashSet<(NodeIndex, bool)> {
+        self.iter()
+            .filter_map(|p| {
+                let decision = p.edge_type.and_then(|e| e.branch_decision())?;
+
+                p.parent
+                    .as_ref()
+                    .map(|parent| (parent.context.node, decision))
+            })
+            .collect()
+    }
+}
+
+/// Partial path in the priority queue of a best first search
+struct Candidate {
+    uncovered: usize,
+    /// Length of the path plus the distance to the nearest root
+    estimate: usize,
+    sequence: usize,
+    partial: Rc<Partial>,
+}
+
+impl Candidate {
+    fn key(&self) -> (usize, Reverse<usize>, usize, Reverse<usize>) {
+        (
+            self.uncovered,
+            Reverse(self.estimate),
+            self.partial.len,
+            Reverse(self.sequence),
+        )
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Partial paths, which are not extended yet
+enum Frontier {
+    Stack(Vec<Rc<Partial>>),
+    Queue(BinaryHeap<Candidate>),
+}
+
+/// Selection of candidate paths by a `SearchStrategy`
+pub struct PathSearch<'a> {
+    cfg: &'a ControlFlowGraph,
+    supergraph: Supergraph<'a>,
+    roots: HashSet<NodeIndex>,
+    /// Number of edges from every instruction to the nearest root
+    distances: HashMap<NodeIndex, usize>,
+    loop_bound: usize,
+    max_call_depth: usize,
+    strategy: SearchStrategy,
+    frontier: Frontier,
+    sequence: usize,
+    random: Random,
+    /// Branch decisions of all selected paths
+    covered: HashSet<(NodeIndex, bool)>,
+}
+
+/// Compute the number of edges from every instruction of `cfg` to the
+/// nearest of `roots` (instructions without a path to a root are missing).
+fn distances_to_roots(cfg: &ControlFlowGraph, roots: &[NodeIndex]) -> HashMap<NodeIndex, usize> {
+    let mut distances = roots
+        .iter()
+        .map(|root| (*root, 0))
+        .collect::<HashMap<_, _>>();
+    let mut queue = roots.iter().copied().collect::<VecDeque<_>>();
+
+    while let Some(node) = queue.pop_front() {
+        let distance = distances[&node] + 1;
+
+        for e in cfg.edges_directed(node, Direction::Incoming) {
+            if let Entry::Vacant(entry) = distances.entry(e.source()) {
+                entry.insert(distance);
+                queue.push_back(e.source());
+            }
+        }
+    }
+
+    distances
+}
+
+#[allow(dead_code)]
+impl<'a> PathSearch<'a> {
+    /// Search the bounded paths of `cfg` from `entry` to all `roots` (see
+    /// `PathEnumerator` for the bounds).
+    pub fn new(
+        cfg: &'a ControlFlowGraph,
+        entry: NodeIndex,
+        roots: &[NodeIndex],
+        loop_bound: usize,
+        max_call_depth: usize,
+        strategy: SearchStrategy,
+    ) -> Self {
+        let seed = match strategy {
+            SearchStrategy::Random { seed } => seed,
+            _ => 0,
+        };
+
+        let frontier = match strategy {
+            SearchStrategy::DepthFirst | SearchStrategy::Random { .. } => Frontier::Stack(vec![]),
+            _ => Frontier::Queue(BinaryHeap::new()),
+        };
+
+        let mut search = Self {
+            cfg,
+            supergraph: Supergraph::new(cfg, max_call_depth),
+            roots: roots.iter().copied().collect(),
+            distances: distances_to_roots(cfg, roots),
+            loop_bound,
+            max_call_depth,
+            strategy,
+            frontier,
+            sequence: 0,
+            random: Random::new(seed),
+            covered: HashSet::new(),
+        };
+
+        let start = Context {
+            node: entry,
+            call_string: CallString::empty(),
+        };
+
+        if loop_bound > 0 && search.distances.contains_key(&entry) {
+            search.insert(vec![Rc::new(Partial {
+                context: start,
+                edge_type: None,
+                len: 1,
+                parent: None,
+            })]);
+        }
+
+        search
+    }
+
+    /// Get the number of branch decisions of `partial`, which are not covered yet.
+    fn uncovered_branches(&self, partial: &Partial) -> usize {
+        match self.strategy {
+            SearchStrategy::UncoveredBranchFirst => {
+                partial.decisions().difference(&self.covered).count()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Add `partials` (in CFG order) to the frontier.
+    fn insert(&mut self, mut partials: Vec<Rc<Partial>>) {
+        if let SearchStrategy::Random { .. } = self.strategy {
+            for i in (1..partials.len()).rev() {
+                partials.swap(i, self.random.below(i + 1));
+            }
+        }
+
+        for partial in partials.into_iter().rev() {
+            let candidate = Candidate {
+                uncovered: self.uncovered_branches(&partial),
+                estimate: partial.len + self.distances[&partial.context.node],
+                sequence: self.sequence,
+                partial,
+            };
+
+            self.sequence += 1;
+
+            match &mut self.frontier {
+                Frontier::Stack(stack) => stack.push(candidate.partial),
+                Frontier::Queue(queue) => queue.push(candidate),
+            }
+        }
+    }
+
+    /// Remove the partial path, which is extended next, from the frontier.
+    fn select(&mut self) -> Option<Rc<Partial>> {
+        loop {
+            let candidate = match &mut self.frontier {
+                Frontier::Stack(stack) => return stack.pop(),
+                Frontier::Queue(queue) => queue.pop()?,
+            };
+
+            // covered branches only grow, hence outdated priorities are too high
+            let uncovered = self.uncovered_branches(&candidate.partial);
+
+            if uncovered == candidate.uncovered {
+                return Some(candidate.partial);
+            }
+
+            if let Frontier::Queue(queue) = &mut self.frontier {
+                queue.push(Candidate {
+                    uncovered,
+                    ..candidate
+                });
+            }
+        }
+    }
+
+    /// Add all extensions of `partial` by one instruction, which are within
+    /// the bounds and might reach a root, to the frontier.
+    fn extend(&mut self, partial: &Rc<Partial>) {
+        let depth = partial.context.call_string.call_sites().len();
+
+        let mut successors = self.supergraph.successors(&partial.context);
+
+        // extend in CFG order (fall through before branch targets)
+        successors.sort_by_key(|(context, _)| context.node);
+
+        let extensions = successors
+            .into_iter()
+            .filter(|(_, edge_type)| *edge_type != EdgeType::Call || depth < self.max_call_depth)
+            .filter(|(context, _)| self.distances.contains_key(&context.node))
+            .filter(|(context, _)| {
+                partial.iter().filter(|p| p.context == *context).count() < self.loop_bound
+            })
+            .map(|(context, edge_type)| {
+                Rc::new(Partial {
+                    context,
+                    edge_type: Some(edge_type),
+                    len: partial.len + 1,
+                    parent: Some(partial.clone()),
+                })
+            })
+            .collect();
+
+        self.insert(extensions);
+    }
+
+    /// Check if `partial` ends in a root, which does not occur before.
+    fn is_candidate(&self, partial: &Partial) -> bool {
+        let node = partial.context.node;
+
+        self.roots.contains(&node) && partial.iter().skip(1).all(|p| p.context.node != node)
+    }
+
+    fn path(&self, partial: &Partial) -> Path {
+        let mut steps = partial.iter().collect::<Vec<_>>();
+        let mut path = Path::new();
+
+        steps.reverse();
+        steps
+            .iter()
+            .for_each(|p| path.push(self.cfg, p.context.node, p.edge_type));
+
+        path
+    }
+}
+
+impl<'a> Iterator for PathSearch<'a> {
+    type Item = Path;
+
+    fn next(&mut self) -> Option<Path> {
+        loop {
+            let partial = self.select()?;
+
+            self.extend(&partial);
+
+            if self.is_candidate(&partial) {
+                self.covered.extend(partial.decisions());
+
+                return Some(self.path(&partial));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candidate_path::find_roots;
+    use crate::cfg::{self, assemble};
+    use crate::elf::ElfMetadata;
+
+    fn elf_metadata(code: &[u8]) -> ElfMetadata {
+        ElfMetadata {
+            entry_address: 0,
+            code_length: code.len() as u64,
+            code_start: 0,
+            functions: vec![],
+        }
+    }
+
+    fn search(strategy: SearchStrategy) -> Vec<Vec<usize>> {
+        let code = assemble(&[
+            0x00050863, // beq a0, zero, 16
+            0x02b55533, // divu a0, a0, a1
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+        ]);
+        let graph = cfg::build(code.as_slice(), 0);
+        let roots = find_roots(&graph, &elf_metadata(code.as_slice()));
+
+        PathSearch::new(&graph, NodeIndex::new(0), roots.as_slice(), 1, 0, strategy)
+            .map(|path| path.nodes().iter().map(|n| n.index()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn can_order_candidate_paths_by_strategy() {
+        let depth_first = vec![vec![0, 1], vec![0, 1, 2, 3], vec![0, 4, 5]];
+        let shortest_first = vec![vec![0, 1], vec![0, 4, 5], vec![0, 1, 2, 3]];
+
+        assert_eq!(search(SearchStrategy::DepthFirst), depth_first);
+        assert_eq!(search(SearchStrategy::ShortestFirst), shortest_first);
+        assert_eq!(
+            search(SearchStrategy::UncoveredBranchFirst),
+            shortest_first,
+            "the taken branch is not covered by the first path"
+        );
+
+        let random = search(SearchStrategy::Random { seed: 42 });
+
+        assert_eq!(random, search(SearchStrategy::Random { seed: 42 }));
+        assert!(depth_first.iter().all(|path| random.contains(path)));
+    }
+
+    #[test]
+    fn can_select_first_path_among_exponentially_many() {
+        // 30 diamonds (beq skips the addi or not) yield 2^30 paths to the exit
+        let mut words = vec![];
+
+        for _ in 0..30 {
+            words.push(0x00050463); // beq a0, zero, 8
+            words.push(0x00158593); // addi a1, a1, 1
+        }
+
+        words.push(0x05d00893); // addi a7, zero, 93
+        words.push(0x00000073); // ecall
+
+        let code = assemble(words.as_slice());
+        let graph = cfg::build(code.as_slice(), 0);
+        let roots = find_roots(&graph, &elf_metadata(code.as_slice()));
+
+        let first = |strategy| {
+            PathSearch::new(&graph, NodeIndex::new(0), roots.as_slice(), 1, 0, strategy)
+                .next()
+                .unwrap()
+        };
+
+        assert_eq!(first(SearchStrategy::ShortestFirst).len(), 32, "all taken");
+
+        for strategy in &[
+            SearchStrategy::DepthFirst,
+            SearchStrategy::Random { seed: 42 },
+            SearchStrategy::UncoveredBranchFirst,
+        ] {
+            assert_eq!(
+                first(*strategy).nodes().last(),
+                Some(&NodeIndex::new(61)),
+                "{:?}",
+                strategy
+            );
+        }
+    }
+}
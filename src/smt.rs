@@ -0,0 +1,258 @@
+//! # Handle SMT-LIB2 export of formulas
+//!
+//! A `Formula` and its roots (see `formula_graph::build_dataflow_graph`) are
+//! printed as `QF_BV` query, which is satisfiable if and only if an input
+//! follows the path and satisfies the bug condition:
+//! - every `Input` node is declared as 64-bit bit vector (`declare-fun`)
+//! - nodes with more than one use are shared by a `define-fun`, all other
+//!   nodes are inlined into their use
+//! - every root is asserted
+//!
+//! Nodes, which are not reachable from a root, are omitted. Formulas with
+//! instructions, which have no `QF_BV` term, or with malformed nodes are
+//! rejected with an error.
+
+use crate::formula_graph::{ArgumentSide, BooleanFunction, Formula, Node};
+use petgraph::algo::toposort;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use riscv_decode::Instruction;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Printer of a single formula, which tracks the names of shared nodes
+struct Printer<'a> {
+    formula: &'a Formula,
+    names: HashMap<NodeIndex, String>,
+}
+
+impl<'a> Printer<'a> {
+    fn argument(&self, node: NodeIndex, side: ArgumentSide) -> Result<NodeIndex, String> {
+        self.formula
+            .edges_directed(node, Direction::Incoming)
+            .find(|e| *e.weight() == side)
+            .map(|e| e.source())
+            .ok_or_else(|| format!("node {} has no {:?} argument", node.index(), side))
+    }
+
+    /// Print `node` as term, where shared nodes are referenced by name.
+    fn term(&self, node: NodeIndex) -> Result<String, String> {
+        if let Some(name) = self.names.get(&node) {
+            return Ok(name.clone());
+        }
+
+        let lhs = || self.term(self.argument(node, ArgumentSide::Lhs)?);
+        let rhs = || self.term(self.argument(node, ArgumentSide::Rhs)?);
+
+        Ok(match &self.formula[node] {
+            Node::Constant(c) => format!("(_ bv{} 64)", c.value),
+            Node::Input(i) => return Err(format!("input {} is not declared", i.name)),
+            Node::Instruction(i) => match i.instruction {
+                Instruction::Addi(_) | Instruction::Add(_) => {
+                    format!("(bvadd {} {})", lhs()?, rhs()?)
+                }
+                Instruction::Sub(_) => format!("(bvsub {} {})", lhs()?, rhs()?),
+                Instruction::Mul(_) => format!("(bvmul {} {})", lhs()?, rhs()?),
+                Instruction::Divu(_) => format!("(bvudiv {} {})", lhs()?, rhs()?),
+                Instruction::Remu(_) => format!("(bvurem {} {})", lhs()?, rhs()?),
+                Instruction::Sltu(_) => {
+                    format!("(ite (bvult {} {}) (_ bv1 64) (_ bv0 64))", lhs()?, rhs()?)
+                }
+                instruction => {
+                    return Err(format!("instruction {:?} has no SMT-LIB term", instruction))
+                }
+            },
+            Node::Constrain(c) => match c.op {
+                BooleanFunction::Not => format!("(not {})", lhs()?),
+                BooleanFunction::NotEquals => format!("(not (= {} {}))", lhs()?, rhs()?),
+                op => format!("({} {} {})", operator(op), lhs()?, rhs()?),
+            },
+        })
+    }
+}
+
+/// SMT-LIB2 function of a binary BooleanFunction
+fn operator(op: BooleanFunction) -> &'static str {
+    match op {
+        BooleanFunction::Equals => "=",
+        BooleanFunction::GreaterThan => "bvugt",
+        BooleanFunction::GreaterThanOrEqual => "bvuge",
+        BooleanFunction::LessThan => "bvult",
+        BooleanFunction::LessThanOrEqual => "bvule",
+        BooleanFunction::SignedGreaterThan => "bvsgt",
+        BooleanFunction::SignedGreaterThanOrEqual => "bvsge",
+        BooleanFunction::SignedLessThan => "bvslt",
+        BooleanFunction::SignedLessThanOrEqual => "bvsle",
+        BooleanFunction::And => "and",
+        BooleanFunction::Or => "or",
+        BooleanFunction::NotEquals | BooleanFunction::Not => unreachable!(),
+    }
+}
+
+/// Get all nodes of `formula`, which are reachable from `roots` (against the
+/// direction of edges).
+fn reachable_nodes(formula: &Formula, roots: &[NodeIndex]) -> HashSet<NodeIndex> {
+    let mut visited = HashSet::new();
+    let mut stack = roots.to_vec();
+
+    while let Some(node) = stack.pop() {
+        if visited.insert(node) {
+            stack.extend(formula.neighbors_directed(node, Direction::Incoming));
+        }
+    }
+
+    visited
+}
+
+/// Print `formula` with asserted `roots` as SMT-LIB2 query.
+pub fn to_smtlib(formula: &Formula, roots: &[NodeIndex]) -> Result<String, String> {
+    let reachable = reachable_nodes(formula, roots);
+    let order = toposort(formula, None)
+        .map_err(|cycle| format!("formula has a cycle at node {}", cycle.node_id().index()))?;
+
+    let mut printer = Printer {
+        formula,
+        names: HashMap::new(),
+    };
+
+    let mut lines = vec![
+        String::from("(set-option :produce-models true)"),
+        String::from("(set-option :incremental true)"),
+        String::from("(set-logic QF_BV)"),
+        String::new(),
+    ];
+
+    for node in order.into_iter().filter(|n| reachable.contains(n)) {
+        let uses = formula
+            .neighbors_directed(node, Direction::Outgoing)
+            .filter(|n| reachable.contains(n))
+            .count();
+
+        let name = match &formula[node] {
+            Node::Input(i) => {
+                let name = format!("x{}", node.index());
+
+                lines.push(format!(
+                    "(declare-fun {} () (_ BitVec 64)) ; {}",
+                    name, i.name
+                ));
+
+                name
+            }
+            Node::Constant(_) => continue,
+            _ if uses > 1 => {
+                let name = format!("t{}", node.index());
+                let sort = match formula[node] {
+                    Node::Constrain(_) => "Bool",
+                    _ => "(_ BitVec 64)",
+                };
+
+                lines.push(format!(
+                    "(define-fun {} () {} {})",
+                    name,
+                    sort,
+                    printer.term(node)?
+                ));
+
+                name
+            }
+            _ => continue,
+        };
+
+        printer.names.insert(node, name);
+    }
+
+    lines.push(String::new());
+
+    for root in roots {
+        lines.push(format!("(assert {})", printer.term(*root)?));
+    }
+
+    lines.extend(vec![
+        String::new(),
+        String::from("(check-sat)"),
+        String::from("(get-model)"),
+        String::new(),
+        String::from("(exit)"),
+    ]);
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Write `formula` with asserted `roots` as SMT-LIB2 query to `file` Path.
+pub fn write_to_file(formula: &Formula, roots: &[NodeIndex], file: &Path) -> Result<(), String> {
+    let query = to_smtlib(formula, roots)?;
+
+    File::create(file)
+        .and_then(|mut file| file.write_all(query.as_bytes()))
+        .map_err(|e| format!("cannot write {}: {}", file.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formula_graph::{build_dataflow_graph, decode_all, elf_metadata, Input, Instr};
+
+    #[test]
+    fn can_print_formula_with_shared_terms() {
+        let path = decode_all(&[
+            0x03f00893, // addi a7, zero, 63
+            0x40000593, // addi a1, zero, 1024
+            0x00100613, // addi a2, zero, 1
+            0x00000073, // ecall
+            0x0005b503, // ld a0, 0(a1)
+            0xfd050513, // addi a0, a0, -48
+            0x00050463, // beq a0, zero, 8
+            0x00a03533, // sltu a0, zero, a0
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+        ]);
+
+        let (formula, roots) = build_dataflow_graph(path.as_slice(), &[false], &[], elf_metadata())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            to_smtlib(&formula, roots.as_slice()).unwrap(),
+            "(set-option :produce-models true)\n\
+             (set-option :incremental true)\n\
+             (set-logic QF_BV)\n\
+             \n\
+             (declare-fun x0 () (_ BitVec 64)) ; read(0, 1024, 1)\n\
+             (define-fun t2 () (_ BitVec 64) (bvadd x0 (_ bv18446744073709551568 64)))\n\
+             \n\
+             (assert (not (= t2 (_ bv0 64))))\n\
+             (assert (bvugt (ite (bvult (_ bv0 64) t2) (_ bv1 64) (_ bv0 64)) (_ bv0 64)))\n\
+             \n\
+             (check-sat)\n\
+             (get-model)\n\
+             \n\
+             (exit)\n"
+        );
+    }
+
+    #[test]
+    fn can_reject_instructions_without_term() {
+        let mut formula = Formula::new();
+
+        let input = formula.add_node(Node::Input(Input {
+            name: String::from("x"),
+        }));
+        let shift = formula.add_node(Node::Instruction(Instr {
+            instruction: decode_all(&[0x00351513])[0], // slli a0, a0, 3
+        }));
+
+        formula.add_edge(input, shift, ArgumentSide::Lhs);
+
+        let error = to_smtlib(&formula, &[shift]).unwrap_err();
+
+        assert!(
+            error.contains("Slli"),
+            "error names the instruction: {}",
+            error
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! # Handle statistics of control flow graphs
+//!
+//! Statistics track how the cost of analyses grows across programs:
+//! - instruction, basic block and function counts
+//! - edge counts by kind (see `export::edge_kind`)
+//! - cyclomatic complexity (`edges - nodes + 2`) and loop nesting of every
+//!   function CFG
+//! - number of syscalls by id (`None` if the id is unknown)
+//! - instructions unreachable from the entry on valid paths (see `supergraph`)
+
+use crate::basic_block::BasicBlocks;
+use crate::call_graph::CallGraph;
+use crate::cfg::{node_at, ControlFlowGraph};
+use crate::dominance::StructuralAnalysis;
+use crate::elf::ElfMetadata;
+use crate::export::edge_kind;
+use crate::supergraph::{CallString, Supergraph};
+use crate::value_analysis::ValueSet;
+use riscv_decode::Instruction;
+use std::collections::BTreeMap;
+
+/// Statistics of a single function
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FunctionStatistics {
+    pub name: String,
+    pub entry_address: u64,
+    pub instructions: usize,
+    pub cyclomatic_complexity: usize,
+    pub loops: usize,
+    /// Maximum nesting depth of loops (0 without loops)
+    pub loop_depth: usize,
+}
+
+/// Statistics of a whole program
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Statistics {
+    pub instructions: usize,
+    pub blocks: usize,
+    pub edges: BTreeMap<&'static str, usize>,
+    pub functions: Vec<FunctionStatistics>,
+    pub syscalls: BTreeMap<Option<u64>, usize>,
+    /// Virtual addresses of instructions unreachable from the entry
+    pub unreachable: Vec<u64>,
+}
+
+impl Statistics {
+    /// Compute the statistics of `graph`.
+    pub fn compute(
+        graph: &ControlFlowGraph,
+        call_graph: &CallGraph,
+        elf_metadata: &ElfMetadata,
+    ) -> Self {
+        let mut edges = BTreeMap::new();
+
+        graph
+            .raw_edges()
+            .iter()
+            .for_each(|e| *edges.entry(edge_kind(e.weight)).or_insert(0) += 1);
+
+        let functions = call_graph
+            .graph
+            .node_indices()
+            .map(|function| {
+                let function_cfg = call_graph.function_cfg(graph, function);
+                let structure = StructuralAnalysis::new(&function_cfg.graph, function_cfg.entry);
+
+                FunctionStatistics {
+                    name: call_graph.graph[function].name.clone(),
+                    entry_address: graph[call_graph.graph[function].entry].address,
+                    instructions: function_cfg.graph.node_count(),
+                    cyclomatic_complexity: (function_cfg.graph.edge_count() + 2)
+                        .saturating_sub(function_cfg.graph.node_count()),
+                    loops: structure.loops.len(),
+                    loop_depth: structure.loops.iter().map(|l| l.depth).max().unwrap_or(0),
+                }
+            })
+            .collect();
+
+        let mut syscalls = BTreeMap::new();
+
+        graph
+            .raw_nodes()
+            .iter()
+            .filter(|n| matches!(n.weight.instruction, Instruction::Ecall))
+            .flat_map(|n| match &n.weight.syscall {
+                Some(ValueSet::Values(ids)) => ids.iter().map(|id| Some(*id)).collect(),
+                _ => vec![None],
+            })
+            .for_each(|id| *syscalls.entry(id).or_insert(0) += 1);
+
+        let reachable = node_at(graph, elf_metadata.entry_address)
+            .map(|entry| Supergraph::new(graph, 0).reachable_nodes(entry, &CallString::empty()))
+            .unwrap_or_default();
+
+        Self {
+            instructions: graph.node_count(),
+            blocks: BasicBlocks::build(graph).graph.node_count(),
+            edges,
+            functions,
+            syscalls,
+            unreachable: graph
+                .node_indices()
+                .filter(|idx| !reachable.contains(idx))
+                .map(|idx| graph[idx].address)
+                .collect(),
+        }
+    }
+
+    /// Format the statistics as human readable text.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("instructions: {}", self.instructions),
+            format!("blocks: {}", self.blocks),
+            format!("functions: {}", self.functions.len()),
+            String::from("edges:"),
+        ];
+
+        lines.extend(
+            self.edges
+                .iter()
+                .map(|(kind, count)| format!("  {}: {}", kind, count)),
+        );
+
+        lines.push(String::from("syscalls:"));
+        lines.extend(self.syscalls.iter().map(|(id, count)| match id {
+            Some(id) => format!("  {}: {}", id, count),
+            None => format!("  unknown: {}", count),
+        }));
+
+        lines.push(format!(
+            "unreachable instructions: {}",
+            self.unreachable.len()
+        ));
+        lines.extend(self.unreachable.iter().map(|a| format!("  {:#x}", a)));
+
+        lines.push(String::from(
+            "function                         entry  instructions  complexity  loops  depth",
+        ));
+        lines.extend(self.functions.iter().map(|f| {
+            format!(
+                "{:<24} {:>#14x} {:>13} {:>11} {:>6} {:>6}",
+                f.name,
+                f.entry_address,
+                f.instructions,
+                f.cyclomatic_complexity,
+                f.loops,
+                f.loop_depth
+            )
+        }));
+
+        lines.join("\n")
+    }
+
+    /// Format the statistics as JSON object.
+    pub fn to_json(&self) -> String {
+        let edges = self
+            .edges
+            .iter()
+            .map(|(kind, count)| format!("\"{}\": {}", kind, count))
+            .collect::<Vec<_>>();
+
+        let syscalls = self
+            .syscalls
+            .iter()
+            .map(|(id, count)| match id {
+                Some(id) => format!("\"{}\": {}", id, count),
+                None => format!("\"unknown\": {}", count),
+            })
+            .collect::<Vec<_>>();
+
+        let unreachable = self
+            .unreachable
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>();
+
+        let functions = self
+            .functions
+            .iter()
+            .map(|f| {
+                format!(
+                    "    {{ \"name\": \"{}\", \"entry_address\": {}, \"instructions\": {}, \
+                     \"cyclomatic_complexity\": {}, \"loops\": {}, \"loop_depth\": {} }}",
+                    f.name.replace('\\', "\\\\").replace('"', "\\\""),
+                    f.entry_address,
+                    f.instructions,
+                    f.cyclomatic_complexity,
+                    f.loops,
+                    f.loop_depth
+                )
+            })
+            .collect::<Vec<_>>();
+
+        format!(
+            "{{\n  \"instructions\": {},\n  \"blocks\": {},\n  \"edges\": {{ {} }},\n  \
+             \"syscalls\": {{ {} }},\n  \"unreachable\": [{}],\n  \"functions\": [\n{}\n  ]\n}}",
+            self.instructions,
+            self.blocks,
+            edges.join(", "),
+            syscalls.join(", "),
+            unreachable.join(", "),
+            functions.join(",\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+
+    #[test]
+    fn can_count_loops_syscalls_and_unreachable_code() {
+        let graph = cfg::build(
+            assemble(&[
+                0x00300513, // addi a0, zero, 3
+                0xfff50513, // loop: addi a0, a0, -1
+                0x00050463, // beq a0, zero, 8
+                0xff9ff06f, // jal zero, loop
+                0x05d00893, // addi a7, zero, 93
+                0x00000073, // ecall
+                0x00000013, // nop
+            ])
+            .as_slice(),
+            0x10000,
+        );
+        let elf_metadata = ElfMetadata {
+            entry_address: 0x10000,
+            code_length: 28,
+            code_start: 0x10000,
+            functions: vec![],
+        };
+        let call_graph = CallGraph::build(&graph, &elf_metadata);
+
+        let stats = Statistics::compute(&graph, &call_graph, &elf_metadata);
+
+        assert_eq!(stats.instructions, 7);
+        assert_eq!(stats.edges.get("branch-taken"), Some(&1));
+        assert_eq!(stats.syscalls.get(&Some(93)), Some(&1));
+        assert_eq!(stats.unreachable, vec![0x10018]);
+        assert_eq!(
+            stats.functions,
+            vec![FunctionStatistics {
+                name: String::from("0x10000"),
+                entry_address: 0x10000,
+                instructions: 6,
+                cyclomatic_complexity: 2,
+                loops: 1,
+                loop_depth: 1,
+            }]
+        );
+        assert!(stats.to_json().contains("\"syscalls\": { \"93\": 1 }"));
+        assert!(stats
+            .to_text()
+            .contains("unreachable instructions: 1\n  0x10018"));
+        assert!(
+            stats.to_text().contains(" 0x10000 "),
+            "entry is printed in hex"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! # Handle interprocedural supergraphs
+//!
+//! The `ControlFlowGraph` connects every return site of a function with the
+//! instruction after every call site of that function. Following its edges
+//! blindly therefore allows infeasible paths (call from A, return to B).
+//!
+//! A supergraph only follows valid paths: every `Return(call_site)` edge has
+//! to match the most recent `Call` edge, which left `call_site` (matched
+//! parentheses). It offers two kinds of queries:
+//! - exact reachability of instructions, which summarizes calls with the
+//!   return sites of the callee (see `cfg::compute_function_boundaries`)
+//! - successors of an instruction in a calling context, which is a call
+//!   string limited to the `k` most recent call sites
+
+use crate::cfg::{compute_function_boundaries, ControlFlowGraph, EdgeType, FunctionBoundary};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// Stack of call sites (most recent last)
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CallString {
+    call_sites: Vec<NodeIndex>,
+    /// `false` if call sites below the stack are unknown (because the call
+    /// string was truncated or did not start at the program entry)
+    complete: bool,
+}
+
+#[allow(dead_code)]
+impl CallString {
+    /// Call string of the program entry, where returns are infeasible
+    pub fn empty() -> Self {
+        Self {
+            call_sites: vec![],
+            complete: true,
+        }
+    }
+
+    /// Call string of an instruction with unknown callers, which may return
+    /// to any of them
+    pub fn unknown() -> Self {
+        Self {
+            call_sites: vec![],
+            complete: false,
+        }
+    }
+
+    pub fn call_sites(&self) -> &[NodeIndex] {
+        self.call_sites.as_slice()
+    }
+
+    /// Push `call_site` and keep at most the `k` most recent call sites.
+    fn push(&self, call_site: NodeIndex, k: usize) -> Self {
+        let mut next = self.clone();
+
+        next.call_sites.push(call_site);
+
+        if next.call_sites.len() > k {
+            next.call_sites.remove(0);
+            next.complete = false;
+        }
+
+        next
+    }
+
+    /// Pop `call_site` if returning to it is feasible.
+    fn pop(&self, call_site: NodeIndex) -> Option<Self> {
+        match self.call_sites.last() {
+            Some(top) if *top == call_site => {
+                let mut next = self.clone();
+                next.call_sites.pop();
+                Some(next)
+            }
+            None if !self.complete => Some(self.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// An instruction in a calling context
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Context {
+    pub node: NodeIndex,
+    pub call_string: CallString,
+}
+
+/// Valid paths of a `ControlFlowGraph`
+pub struct Supergraph<'a> {
+    cfg: &'a ControlFlowGraph,
+    /// Maximum length of call strings
+    k: usize,
+    boundaries: BTreeMap<NodeIndex, FunctionBoundary>,
+}
+
+#[allow(dead_code)]
+impl<'a> Supergraph<'a> {
+    /// Create the supergraph of `cfg` with call strings limited to `k` call sites.
+    pub fn new(cfg: &'a ControlFlowGraph, k: usize) -> Self {
+        let entries = cfg
+            .edge_references()
+            .filter(|e| *e.weight() == EdgeType::Call)
+            .map(|e| e.target())
+            .collect::<BTreeSet<_>>();
+
+        Self {
+            cfg,
+            k,
+            boundaries: compute_function_boundaries(cfg, &entries),
+        }
+    }
+
+    /// Get all successors of `context`, which are reachable on valid paths.
+    pub fn successors(&self, context: &Context) -> Vec<(Context, EdgeType)> {
+        self.cfg
+            .edges(context.node)
+            .filter_map(|e| {
+                let call_string = match *e.weight() {
+                    EdgeType::Call => Some(context.call_string.push(e.source(), self.k)),
+                    EdgeType::Return(call_site) => context.call_string.pop(call_site),
+                    _ => Some(context.call_string.clone()),
+                }?;
+
+                Some((
+                    Context {
+                        node: e.target(),
+                        call_string,
+                    },
+                    *e.weight(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Get all contexts reachable from `from` on valid paths.
+    pub fn reachable_contexts(&self, from: Context) -> HashSet<Context> {
+        let mut visited = HashSet::new();
+        let mut worklist = vec![from];
+
+        while let Some(context) = worklist.pop() {
+            if visited.contains(&context) {
+                continue;
+            }
+
+            worklist.extend(self.successors(&context).into_iter().map(|(c, _)| c));
+            visited.insert(context);
+        }
+
+        visited
+    }
+
+    /// Get the instructions after `call_site`, which the callees return to.
+    fn summary(&self, call_site: NodeIndex) -> Vec<NodeIndex> {
+        self.cfg
+            .edges(call_site)
+            .filter(|e| *e.weight() == EdgeType::Call)
+            .filter_map(|e| self.boundaries.get(&e.target()))
+            .flat_map(|callee| callee.return_sites.iter())
+            .flat_map(|return_site| {
+                self.cfg
+                    .edges(*return_site)
+                    .filter(|e| *e.weight() == EdgeType::Return(call_site))
+                    .map(|e| e.target())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Get all instructions reachable from `from` in the calling context
+    /// `call_string` on valid paths (independent of `k`). Returns without a
+    /// pending call are only followed if `call_string` has pending or unknown
+    /// call sites, which are not distinguished.
+    pub fn reachable_nodes(
+        &self,
+        from: NodeIndex,
+        call_string: &CallString,
+    ) -> BTreeSet<NodeIndex> {
+        // `true` as long as no call is pending, so that returns are unmatched
+        let unmatched = !call_string.complete || !call_string.call_sites.is_empty();
+        let mut visited = HashSet::new();
+        let mut worklist = vec![(from, unmatched)];
+
+        while let Some((idx, unmatched)) = worklist.pop() {
+            if !visited.insert((idx, unmatched)) {
+                continue;
+            }
+
+            self.cfg.edges(idx).for_each(|e| match *e.weight() {
+                EdgeType::Call => {
+                    worklist.push((e.target(), false));
+                    worklist.extend(self.summary(idx).into_iter().map(|r| (r, unmatched)));
+                }
+                EdgeType::Return(_) if unmatched => worklist.push((e.target(), true)),
+                EdgeType::Return(_) => {}
+                _ => worklist.push((e.target(), unmatched)),
+            });
+        }
+
+        visited.into_iter().map(|(idx, _)| idx).collect()
+    }
+
+    /// Check if `to` is reachable from `from` in the calling context
+    /// `call_string` on a valid path.
+    pub fn is_reachable(&self, from: NodeIndex, to: NodeIndex, call_string: &CallString) -> bool {
+        self.reachable_nodes(from, call_string).contains(&to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+
+    // main calls f and exits, f is called from dead code as well
+    fn program() -> ControlFlowGraph {
+        cfg::build(
+            assemble(&[
+                0x014000ef, // jal ra, 20
+                0x05d00893, // addi a7, zero, 93
+                0x00000073, // ecall
+                0x008000ef, // jal ra, 8
+                0x00000013, // nop
+                0x00100513, // addi a0, zero, 1
+                0x00008067, // jalr zero, 0(ra)
+            ])
+            .as_slice(),
+            0,
+        )
+    }
+
+    fn nodes(indices: &[usize]) -> BTreeSet<NodeIndex> {
+        indices.iter().copied().map(NodeIndex::new).collect()
+    }
+
+    #[test]
+    fn can_only_follow_matched_returns() {
+        let cfg = program();
+        let supergraph = Supergraph::new(&cfg, 2);
+
+        assert_eq!(
+            supergraph.reachable_nodes(NodeIndex::new(0), &CallString::empty()),
+            nodes(&[0, 1, 2, 5, 6])
+        );
+        assert!(!supergraph.is_reachable(
+            NodeIndex::new(0),
+            NodeIndex::new(4),
+            &CallString::empty()
+        ));
+        assert!(
+            supergraph.is_reachable(NodeIndex::new(5), NodeIndex::new(4), &CallString::unknown()),
+            "callers of f are unknown"
+        );
+        assert_eq!(
+            supergraph.reachable_nodes(NodeIndex::new(5), &CallString::empty()),
+            nodes(&[5, 6]),
+            "f has no callers"
+        );
+
+        let contexts = supergraph.reachable_contexts(Context {
+            node: NodeIndex::new(0),
+            call_string: CallString::empty(),
+        });
+
+        assert_eq!(
+            contexts.iter().map(|c| c.node).collect::<BTreeSet<_>>(),
+            nodes(&[0, 1, 2, 5, 6])
+        );
+        assert!(contexts.contains(&Context {
+            node: NodeIndex::new(6),
+            call_string: CallString::empty().push(NodeIndex::new(0), 2),
+        }));
+    }
+
+    #[test]
+    fn can_limit_call_strings() {
+        let cfg = program();
+        let supergraph = Supergraph::new(&cfg, 0);
+
+        let contexts = supergraph.reachable_contexts(Context {
+            node: NodeIndex::new(0),
+            call_string: CallString::empty(),
+        });
+
+        assert!(
+            contexts.iter().any(|c| c.node == NodeIndex::new(4)),
+            "context insensitive with k = 0"
+        );
+        assert!(contexts
+            .iter()
+            .all(|c| c.call_string.call_sites().is_empty()));
+    }
+}
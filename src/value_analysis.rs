@@ -0,0 +1,485 @@
+//! # Handle value-set analysis
+//!
+//! This module over-approximates the values of all registers (and of the
+//! double words at known memory addresses) before every instruction of a
+//! `ControlFlowGraph`. A value is either a small set of constants or unknown
+//! (`ValueSet::Top`).
+//!
+//! The analysis is a forward data flow analysis starting at the entry
+//! instruction. It follows all edges of the graph (context insensitive) and
+//! iterates with a worklist until a fixed point is reached. The stack pointer
+//! starts with an arbitrary constant, so that values spilled to the stack
+//! (e.g. function pointers of unoptimized code) are tracked as well.
+//!
+//! The result is used to resolve the targets of indirect jumps (`jalr`).
+
+use crate::cfg::{is_return, ControlFlowGraph};
+use crate::formula_graph::{sign_extend_itype_stype, sign_extend_utype};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use riscv_decode::Instruction;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Maximum number of constants of a `ValueSet` before it becomes `Top`
+pub const MAX_VALUES: usize = 8;
+
+/// Arbitrary value of the stack pointer at the entry of the program
+pub const INITIAL_STACK_POINTER: u64 = 0x8000_0000_0000_0000;
+
+const NUMBER_OF_REGISTERS: usize = 32;
+const REG_SP: u32 = 2;
+const REG_A0: u32 = 10;
+
+/// Possible values of a register or memory location
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValueSet {
+    /// Any value is possible.
+    Top,
+    /// The value is one of these constants.
+    Values(BTreeSet<u64>),
+}
+
+#[allow(dead_code)]
+impl ValueSet {
+    pub fn constant(value: u64) -> Self {
+        ValueSet::Values(vec![value].into_iter().collect())
+    }
+
+    fn from_values(values: BTreeSet<u64>) -> Self {
+        if values.len() > MAX_VALUES {
+            ValueSet::Top
+        } else {
+            ValueSet::Values(values)
+        }
+    }
+
+    /// Get the constants of this set (`None` if any value is possible).
+    pub fn values(&self) -> Option<&BTreeSet<u64>> {
+        match self {
+            ValueSet::Top => None,
+            ValueSet::Values(values) => Some(values),
+        }
+    }
+
+    /// Get the value if it is known exactly.
+    pub fn as_constant(&self) -> Option<u64> {
+        match self.values() {
+            Some(values) if values.len() == 1 => values.iter().next().copied(),
+            _ => None,
+        }
+    }
+
+    /// Least upper bound of two sets
+    pub fn join(&self, other: &ValueSet) -> Self {
+        match (self, other) {
+            (ValueSet::Values(lhs), ValueSet::Values(rhs)) => {
+                Self::from_values(lhs.union(rhs).copied().collect())
+            }
+            _ => ValueSet::Top,
+        }
+    }
+
+    fn map(&self, op: impl Fn(u64) -> u64) -> Self {
+        match self {
+            ValueSet::Top => ValueSet::Top,
+            ValueSet::Values(values) => Self::from_values(values.iter().map(|v| op(*v)).collect()),
+        }
+    }
+
+    fn combine(&self, other: &ValueSet, op: impl Fn(u64, u64) -> u64) -> Self {
+        match (self, other) {
+            (ValueSet::Values(lhs), ValueSet::Values(rhs))
+                if lhs.len() * rhs.len() <= MAX_VALUES =>
+            {
+                ValueSet::Values(
+                    lhs.iter()
+                        .flat_map(|l| rhs.iter().map(move |r| (*l, *r)))
+                        .map(|(l, r)| op(l, r))
+                        .collect(),
+                )
+            }
+            _ => ValueSet::Top,
+        }
+    }
+}
+
+/// Possible values of all registers and known memory locations before an instruction
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AbstractState {
+    registers: Vec<ValueSet>,
+    /// Double words at known addresses (all other memory is `Top`)
+    memory: BTreeMap<u64, ValueSet>,
+}
+
+#[allow(dead_code)]
+impl AbstractState {
+    /// State without any knowledge (except for the zero register)
+    pub fn top() -> Self {
+        let mut state = Self {
+            registers: vec![ValueSet::Top; NUMBER_OF_REGISTERS],
+            memory: BTreeMap::new(),
+        };
+
+        state.registers[0] = ValueSet::constant(0);
+
+        state
+    }
+
+    fn initial() -> Self {
+        let mut state = Self::top();
+
+        state.registers[REG_SP as usize] = ValueSet::constant(INITIAL_STACK_POINTER);
+
+        state
+    }
+
+    /// Get the possible values of register `reg`.
+    pub fn register(&self, reg: u32) -> &ValueSet {
+        &self.registers[reg as usize]
+    }
+
+    /// Get the possible values of the double word at `address`.
+    pub fn memory(&self, address: u64) -> ValueSet {
+        self.memory.get(&address).cloned().unwrap_or(ValueSet::Top)
+    }
+
+    fn set_register(&mut self, reg: u32, value: ValueSet) {
+        // the zero register is hard wired
+        if reg != 0 {
+            self.registers[reg as usize] = value;
+        }
+    }
+
+    fn join(&self, other: &AbstractState) -> Self {
+        Self {
+            registers: self
+                .registers
+                .iter()
+                .zip(other.registers.iter())
+                .map(|(lhs, rhs)| lhs.join(rhs))
+                .collect(),
+            memory: self
+                .memory
+                .iter()
+                .filter_map(|(address, value)| {
+                    other
+                        .memory
+                        .get(address)
+                        .map(|other_value| (*address, value.join(other_value)))
+                })
+                .filter(|(_, value)| *value != ValueSet::Top)
+                .collect(),
+        }
+    }
+
+    fn load(&self, address: &ValueSet) -> ValueSet {
+        match address.values() {
+            Some(addresses) => addresses
+                .iter()
+                .map(|a| self.memory(*a))
+                .fold(ValueSet::Values(BTreeSet::new()), |acc, v| acc.join(&v)),
+            None => ValueSet::Top,
+        }
+    }
+
+    /// Store `value` with `size` bytes. Only double word stores to a single
+    /// address are tracked precisely, every other store only weakens the
+    /// memory locations it might overwrite.
+    fn store(&mut self, address: &ValueSet, size: u64, value: ValueSet) {
+        let addresses = match address.values() {
+            Some(addresses) => addresses.clone(),
+            None => {
+                self.memory.clear();
+                return;
+            }
+        };
+
+        let overlaps = |location: u64, a: u64| {
+            location < a.saturating_add(size) && a < location.saturating_add(8)
+        };
+
+        let strong = if size == 8 && addresses.len() == 1 {
+            addresses.iter().next().copied()
+        } else {
+            None
+        };
+
+        let weak = self
+            .memory
+            .iter()
+            .filter(|(location, _)| Some(**location) != strong)
+            .filter(|(location, _)| addresses.iter().any(|a| overlaps(**location, *a)))
+            .map(|(location, _)| *location)
+            .collect::<Vec<_>>();
+
+        weak.iter().for_each(|location| {
+            let joined = if size == 8 && addresses.contains(location) {
+                self.memory(*location).join(&value)
+            } else {
+                ValueSet::Top
+            };
+
+            if joined == ValueSet::Top {
+                self.memory.remove(location);
+            } else {
+                self.memory.insert(*location, joined);
+            }
+        });
+
+        if let Some(location) = strong {
+            if value == ValueSet::Top {
+                self.memory.remove(&location);
+            } else {
+                self.memory.insert(location, value);
+            }
+        }
+    }
+}
+
+/// Compute the state after executing `instruction` at address `pc` in `state`.
+fn execute(state: &AbstractState, instruction: Instruction, pc: u64) -> AbstractState {
+    let mut next = state.clone();
+
+    let reg = |r: u32| state.register(r);
+
+    match instruction {
+        Instruction::Lui(u) => {
+            next.set_register(u.rd(), ValueSet::constant(sign_extend_utype(u.imm())))
+        }
+        Instruction::Auipc(u) => next.set_register(
+            u.rd(),
+            ValueSet::constant(pc.wrapping_add(sign_extend_utype(u.imm()))),
+        ),
+        Instruction::Jal(j) => next.set_register(j.rd(), ValueSet::constant(pc + 4)),
+        Instruction::Jalr(i) => next.set_register(i.rd(), ValueSet::constant(pc + 4)),
+        Instruction::Addi(i) => {
+            let imm = sign_extend_itype_stype(i.imm());
+            next.set_register(i.rd(), reg(i.rs1()).map(|v| v.wrapping_add(imm)))
+        }
+        Instruction::Xori(i) => {
+            let imm = sign_extend_itype_stype(i.imm());
+            next.set_register(i.rd(), reg(i.rs1()).map(|v| v ^ imm))
+        }
+        Instruction::Ori(i) => {
+            let imm = sign_extend_itype_stype(i.imm());
+            next.set_register(i.rd(), reg(i.rs1()).map(|v| v | imm))
+        }
+        Instruction::Andi(i) => {
+            let imm = sign_extend_itype_stype(i.imm());
+            next.set_register(i.rd(), reg(i.rs1()).map(|v| v & imm))
+        }
+        Instruction::Slti(i) | Instruction::Sltiu(i) => next.set_register(i.rd(), ValueSet::Top),
+        Instruction::Slli(s) => next.set_register(s.rd(), reg(s.rs1()).map(|v| v << s.shamt())),
+        Instruction::Srli(s) => next.set_register(s.rd(), reg(s.rs1()).map(|v| v >> s.shamt())),
+        Instruction::Srai(s) => next.set_register(
+            s.rd(),
+            reg(s.rs1()).map(|v| ((v as i64) >> s.shamt()) as u64),
+        ),
+        Instruction::Add(r) => next.set_register(
+            r.rd(),
+            reg(r.rs1()).combine(reg(r.rs2()), u64::wrapping_add),
+        ),
+        Instruction::Sub(r) => next.set_register(
+            r.rd(),
+            reg(r.rs1()).combine(reg(r.rs2()), u64::wrapping_sub),
+        ),
+        Instruction::Mul(r) => next.set_register(
+            r.rd(),
+            reg(r.rs1()).combine(reg(r.rs2()), u64::wrapping_mul),
+        ),
+        Instruction::Xor(r) => {
+            next.set_register(r.rd(), reg(r.rs1()).combine(reg(r.rs2()), |l, r| l ^ r))
+        }
+        Instruction::Or(r) => {
+            next.set_register(r.rd(), reg(r.rs1()).combine(reg(r.rs2()), |l, r| l | r))
+        }
+        Instruction::And(r) => {
+            next.set_register(r.rd(), reg(r.rs1()).combine(reg(r.rs2()), |l, r| l & r))
+        }
+        Instruction::Sltu(r) => next.set_register(
+            r.rd(),
+            reg(r.rs1()).combine(reg(r.rs2()), |l, r| if l < r { 1 } else { 0 }),
+        ),
+        Instruction::Divu(r) => next.set_register(
+            r.rd(),
+            reg(r.rs1()).combine(reg(r.rs2()), |l, r| l.checked_div(r).unwrap_or(!0)),
+        ),
+        Instruction::Remu(r) => next.set_register(
+            r.rd(),
+            reg(r.rs1()).combine(reg(r.rs2()), |l, r| l.checked_rem(r).unwrap_or(l)),
+        ),
+        Instruction::Sll(r)
+        | Instruction::Slt(r)
+        | Instruction::Srl(r)
+        | Instruction::Sra(r)
+        | Instruction::Div(r)
+        | Instruction::Rem(r) => next.set_register(r.rd(), ValueSet::Top),
+        Instruction::Ld(i) => {
+            let imm = sign_extend_itype_stype(i.imm());
+            next.set_register(
+                i.rd(),
+                state.load(&reg(i.rs1()).map(|v| v.wrapping_add(imm))),
+            )
+        }
+        Instruction::Lb(i)
+        | Instruction::Lh(i)
+        | Instruction::Lw(i)
+        | Instruction::Lbu(i)
+        | Instruction::Lhu(i)
+        | Instruction::Lwu(i) => next.set_register(i.rd(), ValueSet::Top),
+        Instruction::Sd(s) | Instruction::Sw(s) | Instruction::Sh(s) | Instruction::Sb(s) => {
+            let size = match instruction {
+                Instruction::Sd(_) => 8,
+                Instruction::Sw(_) => 4,
+                Instruction::Sh(_) => 2,
+                _ => 1,
+            };
+            let value = if size == 8 {
+                reg(s.rs2()).clone()
+            } else {
+                ValueSet::Top
+            };
+            let imm = sign_extend_itype_stype(s.imm());
+
+            next.store(&reg(s.rs1()).map(|v| v.wrapping_add(imm)), size, value)
+        }
+        // the result of a syscall is unknown
+        Instruction::Ecall => next.set_register(REG_A0, ValueSet::Top),
+        _ => {}
+    }
+
+    next
+}
+
+/// Compute the possible values before every instruction reachable from the
+/// `entry` instruction of `graph`.
+pub fn propagate_values(
+    graph: &ControlFlowGraph,
+    entry: NodeIndex,
+) -> HashMap<NodeIndex, AbstractState> {
+    let mut states = HashMap::new();
+
+    states.insert(entry, AbstractState::initial());
+
+    let mut worklist = vec![entry];
+
+    while let Some(idx) = worklist.pop() {
+        let next = execute(&states[&idx], graph[idx].instruction, graph[idx].address);
+
+        graph.edges(idx).for_each(|edge| {
+            let target = edge.target();
+
+            let joined = match states.get(&target) {
+                Some(state) => state.join(&next),
+                None => next.clone(),
+            };
+
+            if states.get(&target) != Some(&joined) {
+                states.insert(target, joined);
+                worklist.push(target);
+            }
+        });
+    }
+
+    states
+}
+
+/// Get the possible addresses and the size in bytes of the memory access of
+/// `instruction` (`None` if it is no load or store).
+pub fn memory_access(state: &AbstractState, instruction: Instruction) -> Option<(ValueSet, u64)> {
+    let (rs1, imm, size) = match instruction {
+        Instruction::Ld(i) => (i.rs1(), i.imm(), 8),
+        Instruction::Lw(i) | Instruction::Lwu(i) => (i.rs1(), i.imm(), 4),
+        Instruction::Lh(i) | Instruction::Lhu(i) => (i.rs1(), i.imm(), 2),
+        Instruction::Lb(i) | Instruction::Lbu(i) => (i.rs1(), i.imm(), 1),
+        Instruction::Sd(s) => (s.rs1(), s.imm(), 8),
+        Instruction::Sw(s) => (s.rs1(), s.imm(), 4),
+        Instruction::Sh(s) => (s.rs1(), s.imm(), 2),
+        Instruction::Sb(s) => (s.rs1(), s.imm(), 1),
+        _ => return None,
+    };
+
+    let imm = sign_extend_itype_stype(imm);
+
+    Some((state.register(rs1).map(|v| v.wrapping_add(imm)), size))
+}
+
+/// Get the possible target addresses of a `jalr` (`None` for all other
+/// instructions).
+pub fn jump_targets(state: &AbstractState, instruction: Instruction) -> Option<ValueSet> {
+    match instruction {
+        Instruction::Jalr(i) => {
+            let imm = sign_extend_itype_stype(i.imm());
+
+            Some(state.register(i.rs1()).map(|v| v.wrapping_add(imm) & !1))
+        }
+        _ => None,
+    }
+}
+
+/// Compute the possible target addresses of all reachable indirect jumps
+/// (every `jalr` except function returns) from the `states` computed by
+/// `propagate_values`. An empty set of targets means, that the jump could not
+/// be resolved.
+pub fn resolve_indirect_jumps(
+    graph: &ControlFlowGraph,
+    states: &HashMap<NodeIndex, AbstractState>,
+) -> BTreeMap<NodeIndex, BTreeSet<u64>> {
+    states
+        .iter()
+        .filter(|(idx, _)| !is_return(graph[**idx].instruction))
+        .filter_map(|(idx, state)| {
+            let targets = jump_targets(state, graph[*idx].instruction)?;
+
+            Some((*idx, targets.values().cloned().unwrap_or_default()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{self, assemble};
+
+    #[test]
+    fn can_join_value_sets() {
+        let a = ValueSet::constant(1);
+        let b = ValueSet::constant(2);
+
+        assert_eq!(
+            a.join(&b)
+                .values()
+                .unwrap()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(a.join(&ValueSet::Top), ValueSet::Top);
+
+        let many = (0..=MAX_VALUES as u64)
+            .map(ValueSet::constant)
+            .fold(ValueSet::Values(BTreeSet::new()), |acc, v| acc.join(&v));
+
+        assert_eq!(many, ValueSet::Top, "too many values");
+    }
+
+    #[test]
+    fn can_track_values_spilled_to_the_stack() {
+        let binary = assemble(&[
+            0x00000297, // auipc t0, 0
+            0x01028293, // addi t0, t0, 16
+            0x00513423, // sd t0, 8(sp)
+            0x00813303, // ld t1, 8(sp)
+            0x00030067, // jalr zero, 0(t1)
+        ]);
+
+        let graph = cfg::build(binary.as_slice(), 0x10000);
+        let states = propagate_values(&graph, NodeIndex::new(0));
+
+        assert_eq!(
+            states[&NodeIndex::new(4)].register(6).as_constant(),
+            Some(0x10010)
+        );
+    }
+}